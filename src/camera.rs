@@ -1,18 +1,90 @@
 pub mod camera {
     use std::f32::consts::PI;
+    use crate::character::character::CharacterController;
+    use crate::object::object::gmlib::matrix::*;
 
     // Consts
     const TWO_PI: f32 = 2.0 * PI;
     const HALF_PI: f32 = 0.5 * PI;
 
-    #[repr(C)]
-    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+    pub enum CameraMode {
+        // Yaw and pitch angles, pitch clamped to straight up and down, no roll
+        #[default]
+        Fps,
+        // Free orientation quaternion with yaw, pitch and roll around the local axes
+        Free,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    pub enum Projection {
+        // Vertical field of view in radians
+        Perspective { fov_y: f32, near: f32, far: f32 },
+        // Height of the visible area in world units
+        Orthographic { height: f32, near: f32, far: f32 },
+    }
+
+    impl Default for Projection {
+        fn default() -> Self {
+            Projection::Perspective {
+                fov_y: 70.0_f32.to_radians(),
+                near: 0.01,
+                far: 1000.0,
+            }
+        }
+    }
+
+    impl Projection {
+        // Left-handed, maps the view space z range [near, far] to the [0, 1] depth range of wgpu.
+        pub fn matrix(&self, aspect_ratio: f32) -> Mat4x4 {
+            match *self {
+                Projection::Perspective { fov_y, near, far } => {
+                    let f = 1.0 / (0.5 * fov_y).tan();
+
+                    Mat4x4::from([
+                        f / aspect_ratio, 0.0, 0.0,                 0.0,
+                        0.0,              f,   0.0,                 0.0,
+                        0.0,              0.0, far / (far - near), -near * far / (far - near),
+                        0.0,              0.0, 1.0,                 0.0,
+                    ])
+                }
+                Projection::Orthographic { height, near, far } => {
+                    Mat4x4::from([
+                        2.0 / (height * aspect_ratio), 0.0,          0.0,                 0.0,
+                        0.0,                           2.0 / height, 0.0,                 0.0,
+                        0.0,                           0.0,          1.0 / (far - near), -near / (far - near),
+                        0.0,                           0.0,          0.0,                 1.0,
+                    ])
+                }
+            }
+        }
+    }
+
+    #[derive(Copy, Clone, Debug)]
     pub struct Camera {
         pub position: [f32; 3],
         pub angle_h: f32,
         pub angle_v: f32,
         pub mouse_sensitivity: f32,
-        pub depth_factor: f32,
+        pub projection: Projection,
+        pub aspect_ratio: f32,
+        pub mode: CameraMode,
+        // Only used in CameraMode::Free, use orientation() for the current orientation in any mode
+        pub orientation: Quaternion,
+        // Set by turn_to(), approached by update_turning()
+        target_orientation: Option<Quaternion>,
+        // How fast turn_to() reaches its target, per second
+        pub turn_speed: f32,
+        // Radians per second
+        pub roll_speed: f32,
+        // Movement in world units per second
+        pub velocity: [f32; 3],
+        pub walk_speed: f32,
+        pub sprint_speed: f32,
+        // How fast the velocity reaches the target speed, in units per second squared
+        pub acceleration: f32,
+        // How fast the velocity decays without input, per second
+        pub damping: f32,
     }
 
     impl Camera {
@@ -21,18 +93,95 @@ pub mod camera {
             angle_h: f32,
             angle_v: f32,
             mouse_sensitivity: f32,
-            depth_factor: f32,
+            projection: Projection,
         ) -> Self {
             Self {
                 position,
                 angle_h,
                 angle_v,
                 mouse_sensitivity,
-                depth_factor,
+                projection,
+                aspect_ratio: 1.0,
+                mode: CameraMode::Fps,
+                orientation: yaw_pitch(angle_h, angle_v),
+                target_orientation: None,
+                turn_speed: 8.0,
+                roll_speed: 1.5,
+                velocity: [0.0, 0.0, 0.0],
+                walk_speed: 3.0,
+                sprint_speed: 8.0,
+                acceleration: 40.0,
+                damping: 10.0,
+            }
+        }
+
+        pub fn update_aspect_ratio(&mut self, config: &wgpu::SurfaceConfiguration) {
+            if config.width > 0 && config.height > 0 {
+                self.aspect_ratio = config.width as f32 / config.height as f32;
+            }
+        }
+
+        // The columns of matrix() are the right, up and forward axes of the camera.
+        pub fn view_matrix(&self) -> Mat4x4 {
+            let rotation = self.matrix();
+            let right = Vec3::from([rotation[0][0], rotation[1][0], rotation[2][0]]);
+            let up = Vec3::from([rotation[0][1], rotation[1][1], rotation[2][1]]);
+            let forward = Vec3::from([rotation[0][2], rotation[1][2], rotation[2][2]]);
+            let position = Vec3::from(self.position);
+
+            Mat4x4::from([
+                right.x_1,   right.x_2,   right.x_3,   -(right * position),
+                up.x_1,      up.x_2,      up.x_3,      -(up * position),
+                forward.x_1, forward.x_2, forward.x_3, -(forward * position),
+                0.0,         0.0,         0.0,         1.0,
+            ])
+        }
+
+        pub fn projection_matrix(&self) -> Mat4x4 {
+            self.projection.matrix(self.aspect_ratio)
+        }
+
+        pub fn view_projection_matrix(&self) -> Mat4x4 {
+            self.projection_matrix() * self.view_matrix()
+        }
+
+        // World space ray through a point on the screen given in normalized device
+        // coordinates, x and y from -1 to 1 with y pointing up.
+        pub fn ray(&self, ndc: [f32; 2]) -> Ray {
+            let rotation = self.matrix();
+            let right = Vec3::from([rotation[0][0], rotation[1][0], rotation[2][0]]);
+            let up = Vec3::from([rotation[0][1], rotation[1][1], rotation[2][1]]);
+            let forward = Vec3::from([rotation[0][2], rotation[1][2], rotation[2][2]]);
+            let position = Vec3::from(self.position);
+
+            match self.projection {
+                Projection::Perspective { fov_y, .. } => {
+                    let tan = (0.5 * fov_y).tan();
+                    let direction = forward + right * (ndc[0] * tan * self.aspect_ratio) + up * (ndc[1] * tan);
+
+                    Ray::new(position, direction)
+                }
+                Projection::Orthographic { height, .. } => {
+                    let offset = right * (ndc[0] * 0.5 * height * self.aspect_ratio) + up * (ndc[1] * 0.5 * height);
+
+                    Ray::new(position + offset, forward)
+                }
             }
         }
 
         pub fn matrix(&self) -> [[f32; 3]; 3] {
+            if self.mode == CameraMode::Free {
+                let right = self.orientation.rotate_vector(Vec3::from([1.0, 0.0, 0.0]));
+                let up = self.orientation.rotate_vector(Vec3::from([0.0, 1.0, 0.0]));
+                let forward = self.orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+
+                return [
+                    [right.x_1, up.x_1, forward.x_1],
+                    [right.x_2, up.x_2, forward.x_2],
+                    [right.x_3, up.x_3, forward.x_3],
+                ];
+            }
+
             let sin_h = self.angle_h.sin();
             let cos_h = self.angle_h.cos();
             let sin_v = self.angle_v.sin();
@@ -65,11 +214,204 @@ pub mod camera {
         }
 
         pub fn direction(&self) -> [f32; 3] {
-            [-self.angle_h.sin(), self.angle_v.sin(), self.angle_h.cos()]
+            match self.mode {
+                CameraMode::Fps => [-self.angle_h.sin(), self.angle_v.sin(), self.angle_h.cos()],
+                CameraMode::Free => self.orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0])).to_array(),
+            }
         }
 
         pub fn direction_h(&self) -> [f32; 3] {
-            [-self.angle_h.sin(), 0.0, self.angle_h.cos()]
+            match self.mode {
+                CameraMode::Fps => [-self.angle_h.sin(), 0.0, self.angle_h.cos()],
+                CameraMode::Free => {
+                    let forward = self.orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+                    let forward_h = Vec3::from([forward.x_1, 0.0, forward.x_3]);
+
+                    // Looking straight up or down, fall back to the up axis of the camera
+                    if forward_h.magnitude() > 1e-6 {
+                        forward_h.normalize().to_array()
+                    } else {
+                        let up = self.orientation.rotate_vector(Vec3::from([0.0, 1.0, 0.0]));
+                        (Vec3::from([up.x_1, 0.0, up.x_3]) * -forward.x_2.signum()).normalize().to_array()
+                    }
+                }
+            }
+        }
+
+        // The current orientation in either mode
+        pub fn orientation(&self) -> Quaternion {
+            match self.mode {
+                CameraMode::Fps => yaw_pitch(self.angle_h, self.angle_v),
+                CameraMode::Free => self.orientation,
+            }
+        }
+
+        // Switching to Fps keeps the viewing direction and drops any roll.
+        pub fn set_mode(&mut self, mode: CameraMode) {
+            if mode == self.mode {
+                return;
+            }
+
+            let orientation = self.orientation();
+            self.mode = mode;
+            self.set_orientation(orientation);
+        }
+
+        // Sets the orientation right away, cancelling any turn in progress.
+        pub fn set_orientation(&mut self, orientation: Quaternion) {
+            self.target_orientation = None;
+            self.apply_orientation(orientation);
+        }
+
+        fn apply_orientation(&mut self, orientation: Quaternion) {
+            match self.mode {
+                CameraMode::Fps => {
+                    let forward = orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+                    self.set_direction_angles(forward);
+                }
+                CameraMode::Free => self.orientation = orientation.normalize(),
+            }
+        }
+
+        fn set_direction_angles(&mut self, direction: Vec3) {
+            let direction = direction.normalize();
+            let limit = HALF_PI - 0.000001;
+
+            self.angle_h = f32::atan2(-direction.x_1, direction.x_3);
+            self.angle_v = direction.x_2.clamp(-1.0, 1.0).asin().clamp(-limit, limit);
+        }
+
+        // Yaw, pitch and roll in radians, applied in the order roll, pitch, yaw.
+        pub fn set_yaw_pitch_roll(&mut self, yaw: f32, pitch: f32, roll: f32) {
+            let roll = Quaternion::from_axis_angle(Vec3::from([0.0, 0.0, 1.0]), roll);
+            self.set_orientation(yaw_pitch(yaw, pitch) * roll);
+        }
+
+        // Turns left around the up axis of the camera, in Fps mode around the world up axis.
+        pub fn yaw(&mut self, angle: f32) {
+            self.target_orientation = None;
+
+            match self.mode {
+                CameraMode::Fps => {
+                    self.angle_h += angle;
+                    if self.angle_h >= TWO_PI { self.angle_h -= TWO_PI; }
+                    if self.angle_h <= -TWO_PI { self.angle_h += TWO_PI; }
+                }
+                CameraMode::Free => self.rotate_local(Vec3::from([0.0, 1.0, 0.0]), angle),
+            }
+        }
+
+        // Looks up around the right axis of the camera, clamped in Fps mode.
+        pub fn pitch(&mut self, angle: f32) {
+            self.target_orientation = None;
+
+            match self.mode {
+                CameraMode::Fps => {
+                    let limit = HALF_PI - 0.000001;
+                    self.angle_v = (self.angle_v + angle).clamp(-limit, limit);
+                }
+                CameraMode::Free => self.rotate_local(Vec3::from([1.0, 0.0, 0.0]), angle),
+            }
+        }
+
+        // Rolls around the viewing direction, ignored in Fps mode.
+        pub fn roll(&mut self, angle: f32) {
+            if self.mode == CameraMode::Free {
+                self.target_orientation = None;
+                self.rotate_local(Vec3::from([0.0, 0.0, 1.0]), angle);
+            }
+        }
+
+        fn rotate_local(&mut self, axis: Vec3, angle: f32) {
+            self.orientation = (self.orientation * Quaternion::from_axis_angle(axis, angle)).normalize();
+        }
+
+        // Faces the target point right away, keeping the world y axis up.
+        pub fn look_at(&mut self, target: [f32; 3]) {
+            let direction = Vec3::from(target) - Vec3::from(self.position);
+
+            if direction.magnitude() > f32::EPSILON {
+                self.set_orientation(Quaternion::look_rotation(direction, Vec3::from([0.0, 1.0, 0.0])));
+            }
+        }
+
+        // Starts a smooth turn, see update_turning().
+        pub fn turn_to(&mut self, orientation: Quaternion) {
+            self.target_orientation = Some(orientation.normalize());
+        }
+
+        // Starts a smooth turn towards the target point.
+        pub fn turn_towards(&mut self, target: [f32; 3]) {
+            let direction = Vec3::from(target) - Vec3::from(self.position);
+
+            if direction.magnitude() > f32::EPSILON {
+                self.turn_to(Quaternion::look_rotation(direction, Vec3::from([0.0, 1.0, 0.0])));
+            }
+        }
+
+        pub fn is_turning(&self) -> bool {
+            self.target_orientation.is_some()
+        }
+
+        // Slerps towards the orientation given to turn_to(), at turn_speed.
+        pub fn update_turning(&mut self, delta_time: f32) {
+            let target = match self.target_orientation {
+                Some(target) => target,
+                None => return,
+            };
+
+            let current = self.orientation();
+            let t = 1.0 - (-self.turn_speed * delta_time).exp();
+            let next = current.slerp(target, t);
+
+            self.apply_orientation(next);
+
+            if next.dot(target).abs() > 0.999999 {
+                self.apply_orientation(target);
+                self.target_orientation = None;
+            }
+        }
+
+        // Moves the camera by its velocity over delta_time seconds. The input axes
+        // are right, up and forward, each in the range [-1, 1].
+        // In Free mode the axes are the local axes of the camera.
+        pub fn update_movement(&mut self, input: [f32; 3], sprint: bool, delta_time: f32) {
+            let (right, up, forward) = match self.mode {
+                CameraMode::Fps => {
+                    let direction_h = self.direction_h();
+
+                    (
+                        Vec3::from([direction_h[2], 0.0, -direction_h[0]]),
+                        Vec3::from([0.0, 1.0, 0.0]),
+                        Vec3::from(self.direction()),
+                    )
+                }
+                CameraMode::Free => (
+                    self.orientation.rotate_vector(Vec3::from([1.0, 0.0, 0.0])),
+                    self.orientation.rotate_vector(Vec3::from([0.0, 1.0, 0.0])),
+                    self.orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0])),
+                ),
+            };
+
+            let wish = right * input[0] + up * input[1] + forward * input[2];
+            let mut velocity = Vec3::from(self.velocity);
+
+            if wish.magnitude() > f32::EPSILON {
+                let speed = if sprint { self.sprint_speed } else { self.walk_speed };
+                let change = wish.normalize() * speed - velocity;
+                let max_change = self.acceleration * delta_time;
+
+                velocity = if change.magnitude() > max_change {
+                    velocity + change.normalize() * max_change
+                } else {
+                    velocity + change
+                };
+            } else {
+                velocity = velocity * (-self.damping * delta_time).exp();
+            }
+
+            self.velocity = velocity.to_array();
+            self.move_relative((velocity * delta_time).to_array());
         }
 
         pub fn move_to(&mut self, destination: [f32; 3]) {
@@ -135,17 +477,162 @@ pub mod camera {
         }
     }
 
+    // Which controller drives the camera from the input
+    #[derive(Copy, Clone, Debug)]
+    pub enum CameraController {
+        // Mouse look and WASD movement, flying through geometry (noclip)
+        FirstPerson,
+        // Mouse look and WASD movement on foot, colliding with the scene
+        Walking(CharacterController),
+        Orbit(OrbitController),
+    }
+
+    // Orbits a target point for inspecting models: drag to orbit, scroll to zoom,
+    // drag with shift (or the right mouse button) to pan. Input changes the goal
+    // values, the camera follows them with damping in update().
+    #[derive(Copy, Clone, Debug)]
+    pub struct OrbitController {
+        pub target: [f32; 3],
+        // Radians, same meaning as angle_h and angle_v of the camera,
+        // so a negative pitch looks down onto the target
+        pub yaw: f32,
+        pub pitch: f32,
+        pub distance: f32,
+        goal_target: [f32; 3],
+        goal_yaw: f32,
+        goal_pitch: f32,
+        goal_distance: f32,
+        pub min_distance: f32,
+        pub max_distance: f32,
+        pub min_pitch: f32,
+        pub max_pitch: f32,
+        // Radians per unit of mouse movement
+        pub orbit_sensitivity: f32,
+        // Fraction of the distance per unit of mouse movement
+        pub pan_sensitivity: f32,
+        // Fraction of the distance per scroll line
+        pub zoom_sensitivity: f32,
+        // How fast the camera follows the input, per second
+        pub damping: f32,
+    }
+
+    impl OrbitController {
+        pub fn new(target: [f32; 3], yaw: f32, pitch: f32, distance: f32) -> Self {
+            let mut controller = Self {
+                target,
+                yaw,
+                pitch,
+                distance,
+                goal_target: target,
+                goal_yaw: yaw,
+                goal_pitch: pitch,
+                goal_distance: distance,
+                min_distance: 0.1,
+                max_distance: 500.0,
+                min_pitch: -85.0_f32.to_radians(),
+                max_pitch: 85.0_f32.to_radians(),
+                orbit_sensitivity: 0.005,
+                pan_sensitivity: 0.0015,
+                zoom_sensitivity: 0.1,
+                damping: 15.0,
+            };
+
+            controller.clamp_goals();
+            controller.snap();
+
+            controller
+        }
+
+        // Orbits the point distance units in front of the camera, without moving the camera.
+        pub fn from_camera(camera: &Camera, distance: f32) -> Self {
+            let forward = camera.orientation().rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+            let target = Vec3::from(camera.position) + forward * distance;
+
+            let yaw = f32::atan2(-forward.x_1, forward.x_3);
+            let pitch = forward.x_2.clamp(-1.0, 1.0).asin();
+
+            Self::new(target.to_array(), yaw, pitch, distance)
+        }
+
+        pub fn orbit(&mut self, delta_h: f32, delta_v: f32) {
+            self.goal_yaw += delta_h * self.orbit_sensitivity;
+            self.goal_pitch += delta_v * self.orbit_sensitivity;
+            self.clamp_goals();
+        }
+
+        // Positive lines zoom in
+        pub fn zoom(&mut self, lines: f32) {
+            self.goal_distance *= (1.0 - self.zoom_sensitivity).powf(lines);
+            self.clamp_goals();
+        }
+
+        // Moves the target in the view plane, scaled by the distance so the
+        // target follows the mouse at any zoom level.
+        pub fn pan(&mut self, delta_h: f32, delta_v: f32) {
+            let orientation = yaw_pitch(self.goal_yaw, self.goal_pitch);
+            let right = orientation.rotate_vector(Vec3::from([1.0, 0.0, 0.0]));
+            let up = orientation.rotate_vector(Vec3::from([0.0, 1.0, 0.0]));
+
+            let scale = self.goal_distance * self.pan_sensitivity;
+            let offset = right * (delta_h * scale) + up * (delta_v * scale);
+
+            self.goal_target = (Vec3::from(self.goal_target) + offset).to_array();
+        }
+
+        pub fn set_target(&mut self, target: [f32; 3]) {
+            self.goal_target = target;
+        }
+
+        pub fn set_distance(&mut self, distance: f32) {
+            self.goal_distance = distance;
+            self.clamp_goals();
+        }
+
+        // Jumps to the goal values without damping
+        pub fn snap(&mut self) {
+            self.target = self.goal_target;
+            self.yaw = self.goal_yaw;
+            self.pitch = self.goal_pitch;
+            self.distance = self.goal_distance;
+        }
+
+        fn clamp_goals(&mut self) {
+            self.goal_pitch = self.goal_pitch.clamp(self.min_pitch, self.max_pitch);
+            self.goal_distance = self.goal_distance.clamp(self.min_distance, self.max_distance);
+        }
+
+        // Moves towards the goal values and places the camera.
+        pub fn update(&mut self, camera: &mut Camera, delta_time: f32) {
+            let t = 1.0 - (-self.damping * delta_time).exp();
+            let lerp = |from: f32, to: f32| from + (to - from) * t;
+
+            self.yaw = lerp(self.yaw, self.goal_yaw);
+            self.pitch = lerp(self.pitch, self.goal_pitch);
+            // Zooming feels even when the distance changes by a factor, not by an amount
+            self.distance = (lerp(self.distance.ln(), self.goal_distance.ln())).exp();
+            self.target = std::array::from_fn(|i| lerp(self.target[i], self.goal_target[i]));
+
+            let orientation = yaw_pitch(self.yaw, self.pitch);
+            let forward = orientation.rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+
+            camera.position = (Vec3::from(self.target) - forward * self.distance).to_array();
+            camera.velocity = [0.0, 0.0, 0.0];
+            camera.set_orientation(orientation);
+        }
+    }
+
+    // Yaw around the world y axis after pitch around the x axis, like the angles of Fps mode
+    fn yaw_pitch(yaw: f32, pitch: f32) -> Quaternion {
+        Quaternion::from_axis_angle(Vec3::from([0.0, 1.0, 0.0]), yaw)
+            * Quaternion::from_axis_angle(Vec3::from([1.0, 0.0, 0.0]), pitch)
+    }
+
     #[repr(C)]
     #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
     pub struct CameraUniform {
         pub position: [f32; 3],
         _padding_1: [u32; 1],
-        pub matrix_row_1: [f32; 3],
-        _padding_2: [u32; 1],
-        pub matrix_row_2: [f32; 3],
-        _padding_3: [u32; 1],
-        pub matrix_row_3: [f32; 3],
-        _padding_4: [u32; 1],
+        pub view_projection: [[f32; 4]; 4],
     }
 
     impl CameraUniform {
@@ -153,20 +640,14 @@ pub mod camera {
             CameraUniform {
                 position: camera.position,
                 _padding_1: [0; 1],
-                matrix_row_1: camera.matrix()[0],
-                _padding_2: [0; 1],
-                matrix_row_2: camera.matrix()[1],
-                _padding_3: [0; 1],
-                matrix_row_3: camera.matrix()[2],
-                _padding_4: [0; 1],
+                // WGSL matrices are column-major
+                view_projection: camera.view_projection_matrix().transpose().to_array(),
             }
         }
 
         pub fn update(&mut self, camera: Camera) {
             self.position = camera.position;
-            self.matrix_row_1 = camera.matrix()[0];
-            self.matrix_row_2 = camera.matrix()[1];
-            self.matrix_row_3 = camera.matrix()[2];
+            self.view_projection = camera.view_projection_matrix().transpose().to_array();
         }
     }
 }
@@ -0,0 +1,291 @@
+// First-person character controller.
+//
+// The camera sits at eye height on top of an upright capsule that walks on the
+// colliders of a CollisionWorld. Each tick the capsule moves horizontally, then
+// vertically, and is pushed out of whatever it ends up inside of. Walls remove
+// the part of the velocity going into them, so the capsule slides along them.
+// Ground that is no steeper than max_slope only pushes the capsule up, so it
+// does not slide down slopes while standing. Ledges up to step_height are
+// climbed by retrying a blocked move step_height higher.
+
+pub mod character {
+    use crate::camera::camera::Camera;
+    use crate::collision::collision::{CollisionWorld, Shape};
+    use crate::object::object::gmlib::matrix::*;
+
+    // Penetration resolving passes per move
+    const MAX_ITERATIONS: usize = 4;
+    // Bounds on the substeps of a move, for tiny capsules and huge displacements
+    const MIN_STEP_LENGTH: f32 = 1e-3;
+    const MAX_STEPS: u32 = 64;
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct CharacterController {
+        // Capsule dimensions, the height includes both half spheres
+        pub radius: f32,
+        pub height: f32,
+        // Camera height above the feet
+        pub eye_height: f32,
+        pub walk_speed: f32,
+        pub sprint_speed: f32,
+        // Units per second squared on the ground and in the air
+        pub acceleration: f32,
+        pub air_acceleration: f32,
+        // How fast the character stops on the ground without input, per second
+        pub damping: f32,
+        pub gravity: f32,
+        // Fastest fall, in units per second
+        pub max_fall_speed: f32,
+        pub jump_speed: f32,
+        pub step_height: f32,
+        // Steepest walkable ground, radians
+        pub max_slope: f32,
+        pub velocity: [f32; 3],
+        grounded: bool,
+    }
+
+    impl Default for CharacterController {
+        fn default() -> Self {
+            Self {
+                radius: 0.3,
+                height: 1.8,
+                eye_height: 1.6,
+                walk_speed: 4.0,
+                sprint_speed: 7.0,
+                acceleration: 50.0,
+                air_acceleration: 8.0,
+                damping: 12.0,
+                gravity: 20.0,
+                max_fall_speed: 50.0,
+                jump_speed: 6.5,
+                step_height: 0.35,
+                max_slope: 45.0_f32.to_radians(),
+                velocity: [0.0, 0.0, 0.0],
+                grounded: false,
+            }
+        }
+    }
+
+    impl CharacterController {
+        pub fn is_grounded(&self) -> bool {
+            self.grounded
+        }
+
+        // Bottom of the capsule for a camera at position
+        pub fn feet(&self, position: [f32; 3]) -> [f32; 3] {
+            [position[0], position[1] - self.eye_height, position[2]]
+        }
+
+        // The capsule standing on feet
+        pub fn shape(&self, feet: [f32; 3]) -> Shape {
+            let feet = Vec3::from(feet);
+            let radius = self.radius.min(self.height * 0.5);
+
+            Shape::Capsule {
+                start: feet + Vec3::from([0.0, radius, 0.0]),
+                end: feet + Vec3::from([0.0, self.height - radius, 0.0]),
+                radius,
+            }
+        }
+
+        // input is [right, forward] in -1..1, relative to where the camera looks.
+        pub fn update(
+            &mut self,
+            camera: &mut Camera,
+            collisions: &CollisionWorld,
+            input: [f32; 2],
+            jump: bool,
+            sprint: bool,
+            delta_time: f32,
+        ) {
+            let forward = Vec3::from(camera.direction_h());
+            let right = Vec3::from([forward.x_3, 0.0, -forward.x_1]);
+            let wish = right * input[0] + forward * input[1];
+
+            let mut velocity = Vec3::from(self.velocity);
+            let horizontal = Vec3::from([velocity.x_1, 0.0, velocity.x_3]);
+
+            let horizontal = if wish.magnitude() > f32::EPSILON {
+                let speed = if sprint { self.sprint_speed } else { self.walk_speed };
+                let acceleration = if self.grounded { self.acceleration } else { self.air_acceleration };
+                let change = wish.normalize() * speed - horizontal;
+                let max_change = acceleration * delta_time;
+
+                if change.magnitude() > max_change {
+                    horizontal + change.normalize() * max_change
+                } else {
+                    horizontal + change
+                }
+            } else if self.grounded {
+                horizontal * (-self.damping * delta_time).exp()
+            } else {
+                horizontal
+            };
+
+            velocity.x_1 = horizontal.x_1;
+            velocity.x_3 = horizontal.x_3;
+
+            let jumped = jump && self.grounded;
+            if jumped {
+                velocity.x_2 = self.jump_speed;
+                self.grounded = false;
+            }
+            velocity.x_2 = (velocity.x_2 - self.gravity * delta_time).max(-self.max_fall_speed);
+
+            let start = Vec3::from(self.feet(camera.position));
+            let was_grounded = self.grounded;
+
+            // Horizontal first, climbing ledges when blocked on the ground
+            let horizontal_move = Vec3::from([velocity.x_1, 0.0, velocity.x_3]) * delta_time;
+            let mut feet = self.move_horizontal(collisions, start, horizontal_move, was_grounded, &mut velocity);
+
+            // Then vertical, landing on walkable ground
+            let vertical_move = Vec3::from([0.0, velocity.x_2 * delta_time, 0.0]);
+            let (moved, ground) = self.move_and_slide(collisions, feet, vertical_move, &mut velocity);
+            feet = moved;
+            self.grounded = ground && velocity.x_2 <= 0.0;
+
+            // Stick to the ground when walking down steps and slopes
+            if was_grounded && !self.grounded && !jumped {
+                let mut snapped_velocity = velocity;
+                let (snapped, ground) = self.move_and_slide(
+                    collisions,
+                    feet,
+                    Vec3::from([0.0, -self.step_height, 0.0]),
+                    &mut snapped_velocity,
+                );
+
+                if ground {
+                    feet = snapped;
+                    self.grounded = true;
+                }
+            }
+
+            if self.grounded {
+                velocity.x_2 = 0.0;
+            }
+
+            self.velocity = velocity.to_array();
+            camera.velocity = [0.0, 0.0, 0.0];
+            camera.position = [feet.x_1, feet.x_2 + self.eye_height, feet.x_3];
+        }
+
+        fn move_horizontal(
+            &self,
+            collisions: &CollisionWorld,
+            start: Vec3,
+            displacement: Vec3,
+            grounded: bool,
+            velocity: &mut Vec3,
+        ) -> Vec3 {
+            let mut flat_velocity = *velocity;
+            let (flat, _) = self.move_and_slide(collisions, start, displacement, &mut flat_velocity);
+
+            let travelled = |end: Vec3| Vec3::from([end.x_1 - start.x_1, 0.0, end.x_3 - start.x_3]).magnitude();
+            let blocked = travelled(flat) + 1e-4 < displacement.magnitude();
+
+            if grounded && blocked {
+                let raised = start + Vec3::from([0.0, self.step_height, 0.0]);
+
+                if collisions.query(&self.shape(raised.to_array())).is_empty() {
+                    // The rounded bottom only rests on the edge of a ledge once it is
+                    // well past it, a slow step would never get there
+                    let reach = displacement * (self.radius * 0.5 / displacement.magnitude()).max(1.0);
+
+                    let mut step_velocity = *velocity;
+                    let (over, _) = self.move_and_slide(collisions, raised, reach, &mut step_velocity);
+                    let mut down_velocity = step_velocity;
+                    let (down, ground) = self.move_and_slide(
+                        collisions,
+                        over,
+                        Vec3::from([0.0, -self.step_height, 0.0]),
+                        &mut down_velocity,
+                    );
+
+                    let climbed = down.x_2 - start.x_2;
+                    if ground && climbed <= self.step_height && travelled(down) > travelled(flat) + 1e-4 {
+                        velocity.x_1 = step_velocity.x_1;
+                        velocity.x_3 = step_velocity.x_3;
+                        return down;
+                    }
+                }
+            }
+
+            velocity.x_1 = flat_velocity.x_1;
+            velocity.x_3 = flat_velocity.x_3;
+            flat
+        }
+
+        // Moves in steps no longer than half the radius of the capsule so thin geometry is
+        // not skipped, within the bounds above. Returns where the capsule ended up and
+        // whether it stands on walkable ground.
+        fn move_and_slide(
+            &self,
+            collisions: &CollisionWorld,
+            start: Vec3,
+            displacement: Vec3,
+            velocity: &mut Vec3,
+        ) -> (Vec3, bool) {
+            let step_length = (self.radius.min(self.height * 0.5) * 0.5).max(MIN_STEP_LENGTH);
+            let steps = (displacement.magnitude() / step_length).ceil().clamp(1.0, MAX_STEPS as f32);
+            let step = displacement / steps;
+
+            let mut feet = start;
+            let mut ground = false;
+
+            for _ in 0..steps as u32 {
+                feet = feet + step;
+                ground |= self.resolve(collisions, &mut feet, velocity);
+
+                // Landed, falling any further would only sink into the ground
+                if ground && displacement.x_2 < 0.0 {
+                    break;
+                }
+            }
+
+            (feet, ground)
+        }
+
+        // Pushes the capsule out of the colliders, returns whether it touched walkable ground.
+        fn resolve(&self, collisions: &CollisionWorld, feet: &mut Vec3, velocity: &mut Vec3) -> bool {
+            let min_ground_normal = self.max_slope.cos();
+            let mut ground = false;
+
+            for _ in 0..MAX_ITERATIONS {
+                let contact = collisions.query(&self.shape(feet.to_array()))
+                    .into_iter()
+                    .map(|(_, contact)| contact)
+                    .max_by(|a, b| a.depth.total_cmp(&b.depth));
+
+                let contact = match contact {
+                    Some(contact) if contact.depth > 1e-5 => contact,
+                    _ => break,
+                };
+
+                if contact.normal.x_2 >= min_ground_normal {
+                    // Straight up, so standing on a slope does not slide down
+                    *feet = *feet + Vec3::from([0.0, contact.depth / contact.normal.x_2, 0.0]);
+                    velocity.x_2 = velocity.x_2.max(0.0);
+                    ground = true;
+                } else {
+                    // Steep ground and edges push sideways only, or the capsule would climb them
+                    let push = if contact.normal.x_2 > 0.0 {
+                        let sideways = Vec3::from([contact.normal.x_1, 0.0, contact.normal.x_3]).normalize();
+                        sideways * (contact.depth / (sideways * contact.normal))
+                    } else {
+                        contact.normal * contact.depth
+                    };
+                    *feet = *feet + push;
+
+                    // Keep only the part of the velocity along the surface
+                    let into = *velocity * contact.normal;
+                    if into < 0.0 {
+                        *velocity = *velocity - contact.normal * into;
+                    }
+                }
+            }
+
+            ground
+        }
+    }
+}
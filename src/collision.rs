@@ -0,0 +1,870 @@
+// Tree structure of CollisionWorld:
+//
+// * colliders: BTreeMap<MeshId, Vec<Collider>>
+//   - instance: Option<InstanceId>
+//   - triangles: Vec<[Vec3; 3]>
+//   - flat_edges: Vec<[bool; 3]>
+//   - aabb: Aabb
+//     * min: [f32; 3]
+//     * max: [f32; 3]
+//   - sphere: BoundingSphere
+//     * center: [f32; 3]
+//     * radius: f32
+//   - model: [[f32; 4]; 4]
+// * pairs: Vec<CollisionPair>
+//   - a: MeshId
+//   - b: MeshId
+//   - contacts: Vec<Contact>
+//     * point: [f32; 3]
+//     * normal: [f32; 3]
+//     * depth: f32
+//
+// Colliders keep the triangles of objects flagged with `collision` in world
+// space, one for every copy of the mesh that is drawn, so a mesh with
+// instances gets a collider per instance. Contacts of all copies are merged
+// into one pair per two meshes. The broad phase sweeps the bounding boxes
+// along x, the narrow phase tests triangles with the separating axis theorem
+// or closest points.
+// Edges shared by two triangles of the same plane are flagged as flat, they
+// do not exist on the surface and never give contact normals. Face normals of
+// mesh contacts follow the winding of the triangles, so concave meshes such as
+// rooms push out of the side their faces look at; only edge contacts guess the
+// side from the bounding sphere centers.
+// Contact normals point from the second collider (or the mesh) towards the
+// first (or the shape), the depth is how far to move the first one along it.
+
+pub mod collision {
+    use std::collections::{BTreeMap, HashMap};
+
+    use crate::mesh::mesh::{InstanceId, MeshId, MeshRegistry};
+    use crate::object::object::Object;
+    use crate::object::object::gmlib::matrix::*;
+
+    const EPSILON: f32 = 1e-6;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Aabb {
+        pub min: Vec3,
+        pub max: Vec3,
+    }
+
+    impl Aabb {
+        // None without any points
+        pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Option<Self> {
+            let mut points = points.into_iter();
+            let first = points.next()?;
+
+            Some(points.fold(Self { min: first, max: first }, |aabb, point| Self {
+                min: component_min(aabb.min, point),
+                max: component_max(aabb.max, point),
+            }))
+        }
+
+        pub fn from_triangles(triangles: &[[Vec3; 3]]) -> Option<Self> {
+            Self::from_points(triangles.iter().flatten().copied())
+        }
+
+        pub fn center(&self) -> Vec3 {
+            (self.min + self.max) * 0.5
+        }
+
+        pub fn half_extents(&self) -> Vec3 {
+            (self.max - self.min) * 0.5
+        }
+
+        pub fn intersects(&self, other: &Aabb) -> bool {
+            self.min.x_1 <= other.max.x_1 && self.max.x_1 >= other.min.x_1 &&
+            self.min.x_2 <= other.max.x_2 && self.max.x_2 >= other.min.x_2 &&
+            self.min.x_3 <= other.max.x_3 && self.max.x_3 >= other.min.x_3
+        }
+
+        pub fn contains_point(&self, point: Vec3) -> bool {
+            point.x_1 >= self.min.x_1 && point.x_1 <= self.max.x_1 &&
+            point.x_2 >= self.min.x_2 && point.x_2 <= self.max.x_2 &&
+            point.x_3 >= self.min.x_3 && point.x_3 <= self.max.x_3
+        }
+
+        pub fn merge(&self, other: &Aabb) -> Self {
+            Self {
+                min: component_min(self.min, other.min),
+                max: component_max(self.max, other.max),
+            }
+        }
+
+        // Grows the box by margin on every side
+        pub fn expand(&self, margin: f32) -> Self {
+            let margin = Vec3::from([margin, margin, margin]);
+
+            Self {
+                min: self.min - margin,
+                max: self.max + margin,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct BoundingSphere {
+        pub center: Vec3,
+        pub radius: f32,
+    }
+
+    impl BoundingSphere {
+        // Ritter's approximation, at most a few percent larger than the minimal sphere.
+        // None without any points.
+        pub fn from_points(points: &[Vec3]) -> Option<Self> {
+            let first = *points.first()?;
+            let farthest_from = |from: Vec3| points.iter()
+                .copied()
+                .max_by(|a, b| (*a - from).magnitude().total_cmp(&(*b - from).magnitude()))
+                .unwrap_or(from);
+
+            let a = farthest_from(first);
+            let b = farthest_from(a);
+
+            let mut sphere = Self {
+                center: (a + b) * 0.5,
+                radius: (b - a).magnitude() * 0.5,
+            };
+
+            // Grow the sphere to include the points it misses
+            for &point in points {
+                let distance = (point - sphere.center).magnitude();
+                if distance > sphere.radius {
+                    let radius = (sphere.radius + distance) * 0.5;
+                    sphere.center = sphere.center + (point - sphere.center) * ((radius - sphere.radius) / distance);
+                    sphere.radius = radius;
+                }
+            }
+
+            Some(sphere)
+        }
+
+        pub fn from_triangles(triangles: &[[Vec3; 3]]) -> Option<Self> {
+            let points: Vec<Vec3> = triangles.iter().flatten().copied().collect();
+            Self::from_points(&points)
+        }
+
+        pub fn intersects(&self, other: &BoundingSphere) -> bool {
+            let radius = self.radius + other.radius;
+            let offset = self.center - other.center;
+
+            offset * offset <= radius * radius
+        }
+    }
+
+    // Shapes that can be tested against the triangle meshes of colliders
+    #[derive(Debug, Clone, Copy)]
+    pub enum Shape {
+        Sphere { center: Vec3, radius: f32 },
+        // Line segment from start to end, grown by radius
+        Capsule { start: Vec3, end: Vec3, radius: f32 },
+        Box { center: Vec3, half_extents: Vec3, rotation: Quaternion },
+    }
+
+    impl Shape {
+        pub fn aabb(&self) -> Aabb {
+            match *self {
+                Shape::Sphere { center, radius } => Aabb { min: center, max: center }.expand(radius),
+                Shape::Capsule { start, end, radius } => Aabb {
+                    min: component_min(start, end),
+                    max: component_max(start, end),
+                }.expand(radius),
+                Shape::Box { center, half_extents, rotation } => {
+                    let axes = box_axes(rotation);
+                    let extent = |i: usize| {
+                        let component = |axis: Vec3| axis.to_array()[i].abs();
+                        component(axes[0]) * half_extents.x_1 +
+                        component(axes[1]) * half_extents.x_2 +
+                        component(axes[2]) * half_extents.x_3
+                    };
+                    let extents = Vec3::from([extent(0), extent(1), extent(2)]);
+
+                    Aabb {
+                        min: center - extents,
+                        max: center + extents,
+                    }
+                }
+            }
+        }
+
+        // Contact with a single triangle, None when they do not touch
+        pub fn collide_triangle(&self, triangle: &[Vec3; 3]) -> Option<Contact> {
+            self.collide_mesh_triangle(triangle, [false; 3])
+        }
+
+        fn collide_mesh_triangle(&self, triangle: &[Vec3; 3], flat_edges: [bool; 3]) -> Option<Contact> {
+            match *self {
+                Shape::Sphere { center, radius } => sphere_triangle(center, radius, triangle),
+                Shape::Capsule { start, end, radius } => capsule_triangle(start, end, radius, triangle),
+                Shape::Box { center, half_extents, rotation } => {
+                    box_triangle(center, half_extents, rotation, triangle, flat_edges)
+                }
+            }
+        }
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Contact {
+        pub point: Vec3,
+        // Unit length
+        pub normal: Vec3,
+        // Penetration depth along the normal
+        pub depth: f32,
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct CollisionPair {
+        pub a: MeshId,
+        pub b: MeshId,
+        pub contacts: Vec<Contact>,
+    }
+
+    impl CollisionPair {
+        // The contact that penetrates the furthest
+        pub fn deepest(&self) -> Option<&Contact> {
+            self.contacts.iter().max_by(|a, b| a.depth.total_cmp(&b.depth))
+        }
+    }
+
+    type MeshCollider<'a> = (MeshId, &'a Collider);
+
+    #[derive(Debug, Clone)]
+    pub struct Collider {
+        // The instance of the mesh, None for meshes drawn without instances
+        instance: Option<InstanceId>,
+        triangles: Vec<[Vec3; 3]>,
+        // Per triangle, whether its edges (0-1, 1-2, 2-0) are flat
+        flat_edges: Vec<[bool; 3]>,
+        aabb: Aabb,
+        sphere: BoundingSphere,
+        // Model matrix the triangles were transformed with
+        model: [[f32; 4]; 4],
+    }
+
+    impl Collider {
+        // None for objects without triangles
+        pub fn new(object: &Object, model: Mat4x4) -> Option<Self> {
+            Self::new_instance(object, None, model)
+        }
+
+        fn new_instance(object: &Object, instance: Option<InstanceId>, model: Mat4x4) -> Option<Self> {
+            let transform = |position: Vec3| {
+                let point = model * Vec4::from([position.x_1, position.x_2, position.x_3, 1.0]);
+                Vec3::from([point.x_1, point.x_2, point.x_3])
+            };
+
+            let triangles: Vec<[Vec3; 3]> = object.triangles.iter()
+                .map(|triangle| triangle.vertices.map(|vertex| transform(vertex.position)))
+                .collect();
+
+            Some(Self {
+                instance,
+                aabb: Aabb::from_triangles(&triangles)?,
+                sphere: BoundingSphere::from_triangles(&triangles)?,
+                flat_edges: flat_edges(&triangles),
+                triangles,
+                model: model.to_array(),
+            })
+        }
+
+        pub fn instance(&self) -> Option<InstanceId> {
+            self.instance
+        }
+
+        // World space triangles
+        pub fn triangles(&self) -> &[[Vec3; 3]] {
+            &self.triangles
+        }
+
+        pub fn aabb(&self) -> &Aabb {
+            &self.aabb
+        }
+
+        pub fn sphere(&self) -> &BoundingSphere {
+            &self.sphere
+        }
+
+        // Contacts of the shape with every triangle it touches
+        pub fn collide_shape(&self, shape: &Shape) -> Vec<Contact> {
+            let bounds = shape.aabb();
+            if !self.aabb.intersects(&bounds) {
+                return Vec::new();
+            }
+
+            self.triangles.iter()
+                .zip(self.flat_edges.iter())
+                .filter(|(triangle, _)| triangle_aabb(triangle).intersects(&bounds))
+                .filter_map(|(triangle, flat_edges)| shape.collide_mesh_triangle(triangle, *flat_edges))
+                .collect()
+        }
+
+        // Contacts between intersecting triangles of both meshes, the normals
+        // point from other towards self.
+        pub fn collide(&self, other: &Collider) -> Vec<Contact> {
+            if !self.sphere.intersects(&other.sphere) || !self.aabb.intersects(&other.aabb) {
+                return Vec::new();
+            }
+
+            // Edge axes do not know which side is outside, the meshes roughly do
+            let offset = self.sphere.center - other.sphere.center;
+
+            // Only triangles inside the overlap of both boxes can intersect
+            let overlap = Aabb {
+                min: component_max(self.aabb.min, other.aabb.min),
+                max: component_min(self.aabb.max, other.aabb.max),
+            };
+            let candidates = |collider: &Collider| -> Vec<([Vec3; 3], [bool; 3], Aabb)> {
+                collider.triangles.iter()
+                    .zip(collider.flat_edges.iter())
+                    .map(|(triangle, flat_edges)| (*triangle, *flat_edges, triangle_aabb(triangle)))
+                    .filter(|(_, _, aabb)| aabb.intersects(&overlap))
+                    .collect()
+            };
+            let others = candidates(other);
+
+            let mut contacts = Vec::new();
+            for (triangle, flat_edges, aabb) in candidates(self) {
+                for (other_triangle, other_flat_edges, other_aabb) in others.iter() {
+                    if !aabb.intersects(other_aabb) {
+                        continue;
+                    }
+
+                    let contact = triangle_triangle(
+                        (&triangle, flat_edges),
+                        (other_triangle, *other_flat_edges),
+                        offset,
+                    );
+                    contacts.extend(contact);
+                }
+            }
+
+            contacts
+        }
+    }
+
+    #[derive(Default)]
+    pub struct CollisionWorld {
+        colliders: BTreeMap<MeshId, Vec<Collider>>,
+        pairs: Vec<CollisionPair>,
+    }
+
+    impl CollisionWorld {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Rebuilds the colliders of meshes whose transform changed, drops the ones
+        // of removed meshes and finds the colliding pairs again.
+        pub fn update(&mut self, meshes: &MeshRegistry) {
+            self.colliders.retain(|id, _| meshes.get(*id).is_some_and(|mesh| mesh.object.collision));
+
+            for (id, mesh) in meshes.iter().filter(|(_, mesh)| mesh.object.collision) {
+                let matrices = mesh.world_matrices();
+                let unchanged = self.colliders.get(&id).is_some_and(|colliders| {
+                    colliders.len() == matrices.len() && colliders.iter()
+                        .zip(matrices.iter())
+                        .all(|(collider, (instance, model))| {
+                            collider.instance == *instance && collider.model == model.to_array()
+                        })
+                });
+                if unchanged {
+                    continue;
+                }
+
+                let colliders: Vec<Collider> = matrices.into_iter()
+                    .filter_map(|(instance, model)| Collider::new_instance(&mesh.object, instance, model))
+                    .collect();
+
+                if colliders.is_empty() {
+                    self.colliders.remove(&id);
+                } else {
+                    self.colliders.insert(id, colliders);
+                }
+            }
+
+            let mut pairs: BTreeMap<(MeshId, MeshId), Vec<Contact>> = BTreeMap::new();
+            for ((a, collider_a), (b, collider_b)) in self.collider_pairs() {
+                let contacts = collider_a.collide(collider_b);
+                if !contacts.is_empty() {
+                    pairs.entry((a, b)).or_default().extend(contacts);
+                }
+            }
+
+            self.pairs = pairs.into_iter()
+                .map(|((a, b), contacts)| CollisionPair { a, b, contacts })
+                .collect();
+        }
+
+        // Forces the collider to be rebuilt, for when the geometry changed
+        pub fn invalidate(&mut self, id: MeshId) {
+            self.colliders.remove(&id);
+        }
+
+        // One collider per drawn copy of the mesh, empty for meshes without colliders
+        pub fn get(&self, id: MeshId) -> &[Collider] {
+            self.colliders.get(&id).map_or(&[], Vec::as_slice)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (MeshId, &Collider)> {
+            self.colliders.iter().flat_map(|(id, colliders)| colliders.iter().map(move |collider| (*id, collider)))
+        }
+
+        // Colliders of all meshes and instances
+        pub fn len(&self) -> usize {
+            self.colliders.values().map(Vec::len).sum()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.colliders.is_empty()
+        }
+
+        // Colliding pairs found by the last update(), a is always the smaller id
+        pub fn pairs(&self) -> &[CollisionPair] {
+            &self.pairs
+        }
+
+        // Pairs of meshes whose bounding volumes overlap, sorted by id
+        pub fn broad_phase(&self) -> Vec<(MeshId, MeshId)> {
+            let mut pairs: Vec<(MeshId, MeshId)> = self.collider_pairs()
+                .into_iter()
+                .map(|((a, _), (b, _))| (a, b))
+                .collect();
+
+            pairs.dedup();
+            pairs
+        }
+
+        // Colliders of different meshes whose bounding volumes overlap, the one of the
+        // smaller mesh id comes first. Sweep and prune along the x axis.
+        fn collider_pairs(&self) -> Vec<(MeshCollider<'_>, MeshCollider<'_>)> {
+            let mut sorted: Vec<MeshCollider> = self.iter().collect();
+            sorted.sort_by(|(_, a), (_, b)| a.aabb.min.x_1.total_cmp(&b.aabb.min.x_1));
+
+            let mut pairs = Vec::new();
+            let mut active: Vec<MeshCollider> = Vec::new();
+
+            for (id, collider) in sorted {
+                active.retain(|(_, other)| other.aabb.max.x_1 >= collider.aabb.min.x_1);
+
+                for &(other_id, other) in active.iter() {
+                    if other_id != id && collider.sphere.intersects(&other.sphere) && collider.aabb.intersects(&other.aabb) {
+                        pairs.push(if id < other_id { ((id, collider), (other_id, other)) } else { ((other_id, other), (id, collider)) });
+                    }
+                }
+
+                active.push((id, collider));
+            }
+
+            pairs.sort_by_key(|((a, _), (b, _))| (*a, *b));
+            pairs
+        }
+
+        // Contacts of the shape with all colliders, the normals point towards the shape
+        pub fn query(&self, shape: &Shape) -> Vec<(MeshId, Contact)> {
+            self.iter()
+                .flat_map(|(id, collider)| collider.collide_shape(shape).into_iter().map(move |contact| (id, contact)))
+                .collect()
+        }
+    }
+
+    fn component_min(a: Vec3, b: Vec3) -> Vec3 {
+        Vec3::from([a.x_1.min(b.x_1), a.x_2.min(b.x_2), a.x_3.min(b.x_3)])
+    }
+
+    fn component_max(a: Vec3, b: Vec3) -> Vec3 {
+        Vec3::from([a.x_1.max(b.x_1), a.x_2.max(b.x_2), a.x_3.max(b.x_3)])
+    }
+
+    fn triangle_aabb(triangle: &[Vec3; 3]) -> Aabb {
+        Aabb {
+            min: component_min(component_min(triangle[0], triangle[1]), triangle[2]),
+            max: component_max(component_max(triangle[0], triangle[1]), triangle[2]),
+        }
+    }
+
+    // Outward normal for counter-clockwise triangles, zero for degenerate ones
+    fn triangle_normal(triangle: &[Vec3; 3]) -> Vec3 {
+        normalize_or_zero((triangle[1] - triangle[0]) % (triangle[2] - triangle[0]))
+    }
+
+    fn normalize_or_zero(vector: Vec3) -> Vec3 {
+        let magnitude = vector.magnitude();
+        if magnitude > EPSILON { vector / magnitude } else { vector * 0.0 }
+    }
+
+    fn box_axes(rotation: Quaternion) -> [Vec3; 3] {
+        let rotation = rotation.normalize();
+
+        [
+            rotation.rotate_vector(Vec3::from([1.0, 0.0, 0.0])),
+            rotation.rotate_vector(Vec3::from([0.0, 1.0, 0.0])),
+            rotation.rotate_vector(Vec3::from([0.0, 0.0, 1.0])),
+        ]
+    }
+
+    // Closest point on the triangle to point, from Ericson's Real-Time Collision Detection
+    fn closest_point_on_triangle(point: Vec3, triangle: &[Vec3; 3]) -> Vec3 {
+        let [a, b, c] = *triangle;
+        let ab = b - a;
+        let ac = c - a;
+
+        let ap = point - a;
+        let d1 = ab * ap;
+        let d2 = ac * ap;
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+
+        let bp = point - b;
+        let d3 = ab * bp;
+        let d4 = ac * bp;
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+
+        let cp = point - c;
+        let d5 = ab * cp;
+        let d6 = ac * cp;
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+
+        // Inside the face
+        let denominator = 1.0 / (va + vb + vc);
+        a + ab * (vb * denominator) + ac * (vc * denominator)
+    }
+
+    // Closest points between the segments p1-q1 and p2-q2
+    fn closest_points_on_segments(p1: Vec3, q1: Vec3, p2: Vec3, q2: Vec3) -> (Vec3, Vec3) {
+        let d1 = q1 - p1;
+        let d2 = q2 - p2;
+        let r = p1 - p2;
+        let a = d1 * d1;
+        let e = d2 * d2;
+        let f = d2 * r;
+
+        let (s, t) = if a <= EPSILON && e <= EPSILON {
+            (0.0, 0.0)
+        } else if a <= EPSILON {
+            (0.0, (f / e).clamp(0.0, 1.0))
+        } else {
+            let c = d1 * r;
+            if e <= EPSILON {
+                ((-c / a).clamp(0.0, 1.0), 0.0)
+            } else {
+                let b = d1 * d2;
+                let denominator = a * e - b * b;
+
+                let mut s = if denominator != 0.0 { ((b * f - c * e) / denominator).clamp(0.0, 1.0) } else { 0.0 };
+                let mut t = (b * s + f) / e;
+
+                if t < 0.0 {
+                    t = 0.0;
+                    s = (-c / a).clamp(0.0, 1.0);
+                } else if t > 1.0 {
+                    t = 1.0;
+                    s = ((b - c) / a).clamp(0.0, 1.0);
+                }
+
+                (s, t)
+            }
+        };
+
+        (p1 + d1 * s, p2 + d2 * t)
+    }
+
+    // Contact from the closest points of a shape core and a triangle
+    fn contact_from_closest(core: Vec3, closest: Vec3, radius: f32, triangle: &[Vec3; 3]) -> Option<Contact> {
+        let offset = core - closest;
+        let distance = offset.magnitude();
+        if distance >= radius {
+            return None;
+        }
+
+        // The core touches the triangle, push out along the face
+        let normal = if distance > EPSILON { offset / distance } else { triangle_normal(triangle) };
+
+        Some(Contact {
+            point: closest,
+            normal,
+            depth: radius - distance,
+        })
+    }
+
+    fn sphere_triangle(center: Vec3, radius: f32, triangle: &[Vec3; 3]) -> Option<Contact> {
+        contact_from_closest(center, closest_point_on_triangle(center, triangle), radius, triangle)
+    }
+
+    fn capsule_triangle(start: Vec3, end: Vec3, radius: f32, triangle: &[Vec3; 3]) -> Option<Contact> {
+        let normal = triangle_normal(triangle);
+        let start_distance = normal * (start - triangle[0]);
+        let end_distance = normal * (end - triangle[0]);
+
+        // The segment pierces the triangle, push out of the side the capsule is mostly on
+        if start_distance * end_distance < 0.0 {
+            let t = start_distance / (start_distance - end_distance);
+            let point = start + (end - start) * t;
+
+            if (closest_point_on_triangle(point, triangle) - point).magnitude() <= EPSILON {
+                let (normal, depth) = if start_distance.abs() < end_distance.abs() {
+                    (-normal * start_distance.signum(), start_distance.abs())
+                } else {
+                    (-normal * end_distance.signum(), end_distance.abs())
+                };
+
+                return Some(Contact {
+                    point,
+                    normal,
+                    depth: radius + depth,
+                });
+            }
+        }
+
+        // Otherwise the closest points lie on an edge of the triangle or an end of the segment
+        let mut candidates = vec![
+            (start, closest_point_on_triangle(start, triangle)),
+            (end, closest_point_on_triangle(end, triangle)),
+        ];
+        for i in 0..3 {
+            candidates.push(closest_points_on_segments(start, end, triangle[i], triangle[(i + 1) % 3]));
+        }
+
+        let (core, closest) = candidates.into_iter()
+            .min_by(|(a, a_closest), (b, b_closest)| (*a - *a_closest).magnitude().total_cmp(&(*b - *b_closest).magnitude()))?;
+
+        contact_from_closest(core, closest, radius, triangle)
+    }
+
+    // Interval of the points projected onto axis
+    fn project(points: &[Vec3], axis: Vec3) -> (f32, f32) {
+        points.iter().fold((f32::MAX, f32::MIN), |(min, max), point| {
+            let distance = *point * axis;
+            (min.min(distance), max.max(distance))
+        })
+    }
+
+    fn centroid(points: &[Vec3]) -> Vec3 {
+        points.iter().fold(Vec3::from([0.0, 0.0, 0.0]), |sum, point| sum + *point) / points.len() as f32
+    }
+
+    #[derive(Debug, Clone, Copy)]
+    struct Axis {
+        direction: Vec3,
+        // The axis only wins when its overlap is smaller than the best one by more than this
+        bias: f32,
+        // Already points from the second set towards the first, and only pushes that way
+        oriented: bool,
+    }
+
+    impl Axis {
+        // Flipped to point along the offset
+        fn free(direction: Vec3, bias: f32) -> Self {
+            Self { direction, bias, oriented: false }
+        }
+
+        fn oriented(direction: Vec3) -> Self {
+            Self { direction, bias: 0.0, oriented: true }
+        }
+    }
+
+    // Smallest overlap of the two point sets over all axes, None if one separates them.
+    // The returned axis points from the second set towards the first, free axes are
+    // flipped to point along offset.
+    fn separating_axis_test(a: &[Vec3], b: &[Vec3], axes: &[Axis], offset: Vec3) -> Option<(Vec3, f32)> {
+        let mut best: Option<(Vec3, f32)> = None;
+
+        for axis in axes {
+            let direction = normalize_or_zero(axis.direction);
+            if direction * direction == 0.0 {
+                continue;
+            }
+
+            let (a_min, a_max) = project(a, direction);
+            let (b_min, b_max) = project(b, direction);
+            if a_max - b_min <= 0.0 || b_max - a_min <= 0.0 {
+                return None;
+            }
+
+            let (direction, overlap) = if axis.oriented {
+                (direction, b_max - a_min)
+            } else {
+                let overlap = (a_max - b_min).min(b_max - a_min);
+                (if offset * direction < 0.0 { -direction } else { direction }, overlap)
+            };
+
+            if best.is_none_or(|(_, depth)| overlap + axis.bias < depth) {
+                best = Some((direction, overlap));
+            }
+        }
+
+        best
+    }
+
+    // Average of the points lying furthest along -normal
+    fn deepest_point(points: &[Vec3], normal: Vec3) -> Vec3 {
+        let (min, _) = project(points, normal);
+        let deepest: Vec<Vec3> = points.iter()
+            .copied()
+            .filter(|point| *point * normal <= min + 1e-4)
+            .collect();
+
+        centroid(&deepest)
+    }
+
+    fn box_triangle(
+        center: Vec3,
+        half_extents: Vec3,
+        rotation: Quaternion,
+        triangle: &[Vec3; 3],
+        flat_edges: [bool; 3],
+    ) -> Option<Contact> {
+        let axes = box_axes(rotation);
+        let [x, y, z] = axes;
+        let extents = [x * half_extents.x_1, y * half_extents.x_2, z * half_extents.x_3];
+
+        let mut corners = Vec::with_capacity(8);
+        for i in 0..8 {
+            let sign = |bit: usize| if i & (1 << bit) == 0 { -1.0 } else { 1.0 };
+            corners.push(center + extents[0] * sign(0) + extents[1] * sign(1) + extents[2] * sign(2));
+        }
+
+        let edges = [triangle[1] - triangle[0], triangle[2] - triangle[1], triangle[0] - triangle[2]];
+        let mut separating_axes = vec![
+            Axis::free(axes[0], 0.0),
+            Axis::free(axes[1], 0.0),
+            Axis::free(axes[2], 0.0),
+            Axis::free(triangle_normal(triangle), 0.0),
+        ];
+        for axis in axes {
+            for (edge, flat) in edges.into_iter().zip(flat_edges) {
+                separating_axes.push(Axis::free(axis % edge, edge_bias(flat)));
+            }
+        }
+
+        let (normal, depth) = separating_axis_test(&corners, triangle, &separating_axes, center - centroid(triangle))?;
+
+        Some(Contact {
+            point: deepest_point(&corners, normal),
+            normal,
+            depth,
+        })
+    }
+
+    // offset points from the mesh of b towards the mesh of a, it only orients edge axes.
+    // Face axes push a out of the front of b, or b out of the back of a.
+    // Triangles come with the flat flags of their edges
+    fn triangle_triangle(
+        (a, a_flat): (&[Vec3; 3], [bool; 3]),
+        (b, b_flat): (&[Vec3; 3], [bool; 3]),
+        offset: Vec3,
+    ) -> Option<Contact> {
+        let a_normal = triangle_normal(a);
+        let b_normal = triangle_normal(b);
+        let a_edges = [a[1] - a[0], a[2] - a[1], a[0] - a[2]];
+        let b_edges = [b[1] - b[0], b[2] - b[1], b[0] - b[2]];
+
+        let mut axes = Vec::with_capacity(17);
+        axes.push(Axis::oriented(-a_normal));
+        axes.push(Axis::oriented(b_normal));
+        for (a_edge, a_flat) in a_edges.into_iter().zip(a_flat) {
+            for (b_edge, b_flat) in b_edges.into_iter().zip(b_flat) {
+                axes.push(Axis::free(a_edge % b_edge, edge_bias(a_flat || b_flat)));
+            }
+        }
+
+        // Coplanar triangles are only separated by axes within their plane
+        if (a_normal % b_normal).magnitude() <= EPSILON {
+            axes.extend(a_edges.map(|edge| Axis::free(a_normal % edge, f32::INFINITY)));
+            axes.extend(b_edges.map(|edge| Axis::free(a_normal % edge, f32::INFINITY)));
+        }
+
+        let (normal, depth) = separating_axis_test(a, b, &axes, offset)?;
+
+        let mut crossings = edge_crossings(a, b);
+        crossings.extend(edge_crossings(b, a));
+
+        let point = if crossings.is_empty() {
+            deepest_point(a, normal)
+        } else {
+            centroid(&crossings)
+        };
+
+        Some(Contact {
+            point,
+            normal,
+            depth,
+        })
+    }
+
+    // Face contacts are more stable, edges have to be clearly better. Flat edges
+    // still rule out an intersection but never give the normal.
+    fn edge_bias(flat: bool) -> f32 {
+        if flat { f32::INFINITY } else { 1e-4 }
+    }
+
+    // Edges shared by exactly two triangles facing the same way
+    fn flat_edges(triangles: &[[Vec3; 3]]) -> Vec<[bool; 3]> {
+        let key = |point: Vec3| point.to_array().map(f32::to_bits);
+        let edge_key = |triangle: &[Vec3; 3], edge: usize| {
+            let (start, end) = (key(triangle[edge]), key(triangle[(edge + 1) % 3]));
+            if start <= end { (start, end) } else { (end, start) }
+        };
+
+        let mut shared: HashMap<_, Vec<usize>> = HashMap::new();
+        for (index, triangle) in triangles.iter().enumerate() {
+            for edge in 0..3 {
+                shared.entry(edge_key(triangle, edge)).or_default().push(index);
+            }
+        }
+
+        triangles.iter()
+            .map(|triangle| {
+                std::array::from_fn(|edge| match shared[&edge_key(triangle, edge)].as_slice() {
+                    &[first, second] => {
+                        triangle_normal(&triangles[first]) * triangle_normal(&triangles[second]) > 1.0 - 1e-4
+                    }
+                    _ => false,
+                })
+            })
+            .collect()
+    }
+
+    // Points where the edges of a pass through b, together with the crossings
+    // of b through a they bound the intersection of the two triangles.
+    fn edge_crossings(a: &[Vec3; 3], b: &[Vec3; 3]) -> Vec<Vec3> {
+        let normal = triangle_normal(b);
+        let mut crossings = Vec::new();
+
+        for i in 0..3 {
+            let start = a[i];
+            let end = a[(i + 1) % 3];
+            let start_distance = normal * (start - b[0]);
+            let end_distance = normal * (end - b[0]);
+
+            if start_distance * end_distance < 0.0 {
+                let point = start + (end - start) * (start_distance / (start_distance - end_distance));
+
+                if (closest_point_on_triangle(point, b) - point).magnitude() <= 1e-4 {
+                    crossings.push(point);
+                }
+            }
+        }
+
+        crossings
+    }
+}
@@ -0,0 +1,131 @@
+// Frame statistics.
+//
+// FpsCounter keeps the timings of the last frames in a ring buffer and derives
+// the usual numbers from it. "Lows" are the average frame rate of the slowest
+// 1% / 0.1% of the frames in the window, which shows stutter a plain average hides.
+// Update time is the CPU time spent in State::update, submit time the CPU time
+// spent encoding and submitting the frame to the GPU.
+
+pub mod fps {
+    use std::collections::VecDeque;
+
+    #[derive(Copy, Clone, Debug, Default)]
+    pub struct FrameSample {
+        // All in seconds
+        pub frame_time: f32,
+        pub update_time: f32,
+        pub submit_time: f32,
+    }
+
+    #[derive(Copy, Clone, Debug, Default)]
+    pub struct FrameStats {
+        pub fps: f32,
+        pub low_1_percent: f32,
+        pub low_0_1_percent: f32,
+        // All times in seconds
+        pub average_frame_time: f32,
+        pub min_frame_time: f32,
+        pub max_frame_time: f32,
+        pub average_update_time: f32,
+        pub average_submit_time: f32,
+        pub sample_count: usize,
+    }
+
+    pub struct FpsCounter {
+        samples: VecDeque<FrameSample>,
+        capacity: usize,
+    }
+
+    impl Default for FpsCounter {
+        fn default() -> Self {
+            Self::new(1000)
+        }
+    }
+
+    impl FpsCounter {
+        // Capacity is the number of frames the statistics are computed over.
+        pub fn new(capacity: usize) -> Self {
+            let capacity = capacity.max(1);
+
+            Self {
+                samples: VecDeque::with_capacity(capacity),
+                capacity,
+            }
+        }
+
+        pub fn record(&mut self, sample: FrameSample) {
+            if self.samples.len() == self.capacity {
+                self.samples.pop_front();
+            }
+
+            self.samples.push_back(sample);
+        }
+
+        pub fn clear(&mut self) {
+            self.samples.clear();
+        }
+
+        // Oldest sample first
+        pub fn samples(&self) -> impl ExactSizeIterator<Item = &FrameSample> {
+            self.samples.iter()
+        }
+
+        pub fn last(&self) -> Option<&FrameSample> {
+            self.samples.back()
+        }
+
+        pub fn stats(&self) -> FrameStats {
+            let count = self.samples.len();
+            if count == 0 {
+                return FrameStats::default();
+            }
+
+            let mut frame_times: Vec<f32> = self.samples.iter().map(|sample| sample.frame_time).collect();
+            // Slowest frames first
+            frame_times.sort_by(|a, b| b.total_cmp(a));
+
+            let total: f32 = frame_times.iter().sum();
+            let average_frame_time = total / count as f32;
+
+            FrameStats {
+                fps: frame_rate(average_frame_time),
+                low_1_percent: frame_rate(slowest_average(&frame_times, 0.01)),
+                low_0_1_percent: frame_rate(slowest_average(&frame_times, 0.001)),
+                average_frame_time,
+                min_frame_time: frame_times[count - 1],
+                max_frame_time: frame_times[0],
+                average_update_time: self.samples.iter().map(|sample| sample.update_time).sum::<f32>() / count as f32,
+                average_submit_time: self.samples.iter().map(|sample| sample.submit_time).sum::<f32>() / count as f32,
+                sample_count: count,
+            }
+        }
+
+        // Counts the frame times into buckets of bucket_width seconds,
+        // the last bucket also holds every slower frame.
+        pub fn histogram(&self, bucket_width: f32, bucket_count: usize) -> Vec<u32> {
+            let mut buckets = vec![0; bucket_count];
+            if bucket_count == 0 || bucket_width <= 0.0 {
+                return buckets;
+            }
+
+            for sample in self.samples.iter() {
+                let bucket = ((sample.frame_time / bucket_width) as usize).min(bucket_count - 1);
+                buckets[bucket] += 1;
+            }
+
+            buckets
+        }
+    }
+
+    // Average of the slowest fraction of the frames, at least one frame.
+    // Expects the frame times sorted from slowest to fastest.
+    fn slowest_average(sorted_frame_times: &[f32], fraction: f32) -> f32 {
+        let count = ((sorted_frame_times.len() as f32 * fraction).ceil() as usize).max(1);
+
+        sorted_frame_times[..count].iter().sum::<f32>() / count as f32
+    }
+
+    fn frame_rate(frame_time: f32) -> f32 {
+        if frame_time > 0.0 { 1.0 / frame_time } else { 0.0 }
+    }
+}
@@ -0,0 +1,221 @@
+// glTF 2.0 scene importer, reads both .gltf (+ .bin) and .glb files.
+//
+// Every primitive of every mesh instance in the scene becomes one GltfObject,
+// holding the Object in mesh space and the world transform of its node.
+// glTF is right-handed while the engine is left-handed, so the z axis is
+// flipped on import, just like in the OBJ loader.
+
+pub mod gltf_loader {
+    use std::path::Path;
+    use anyhow::Context;
+
+    use crate::object::object::gmlib::matrix::*;
+    use crate::object::object::{Object, Triangle, Vertex};
+
+    // Converts between right-handed and left-handed coordinates
+    const FLIP_Z: Mat4x4 = Mat4x4 {
+        x_11: 1.0, x_12: 0.0, x_13: 0.0,  x_14: 0.0,
+        x_21: 0.0, x_22: 1.0, x_23: 0.0,  x_24: 0.0,
+        x_31: 0.0, x_32: 0.0, x_33: -1.0, x_34: 0.0,
+        x_41: 0.0, x_42: 0.0, x_43: 0.0,  x_44: 1.0,
+    };
+
+    #[derive(Debug, Clone)]
+    pub struct GltfObject {
+        pub name: Option<String>,
+        pub object: Object,
+        pub transform: Mat4x4,
+        pub base_color: [f32; 4],
+    }
+
+    impl GltfObject {
+        // Applies the node transform to the vertices, for renderers without per-object transforms.
+        pub fn to_world(&self) -> Object {
+            let mut object = self.object.clone();
+            let normal_matrix = self.transform.normal_matrix();
+            // Mirroring transforms flip the winding of the triangles
+            let mirrored = normal_matrix.determinant() < 0.0;
+
+            for triangle in object.triangles.iter_mut() {
+                for vertex in triangle.vertices.iter_mut() {
+                    vertex.position = transform_point(self.transform, vertex.position);
+
+                    let normal = normal_matrix * vertex.normal;
+                    if normal.magnitude() > f32::EPSILON {
+                        vertex.normal = normal.normalize();
+                    }
+                }
+
+                if mirrored {
+                    triangle.vertices.swap(1, 2);
+                }
+
+                let normal = normal_matrix * triangle.normal;
+                if normal.magnitude() > f32::EPSILON {
+                    triangle.normal = normal.normalize();
+                }
+            }
+
+            object
+        }
+    }
+
+    pub fn load_gltf(path: impl AsRef<Path>) -> anyhow::Result<Vec<GltfObject>> {
+        let path = path.as_ref();
+        let (document, buffers, _images) = gltf::import(path)
+            .with_context(|| format!("Unable to import {}", path.display()))?;
+
+        let scene = document.default_scene()
+            .or_else(|| document.scenes().next())
+            .with_context(|| format!("{} contains no scene", path.display()))?;
+
+        let mut objects = Vec::new();
+
+        for node in scene.nodes() {
+            load_node(&node, UNIT_MAT4X4, &buffers, &mut objects)?;
+        }
+
+        Ok(objects)
+    }
+
+    fn load_node(
+        node: &gltf::Node,
+        parent_transform: Mat4x4,
+        buffers: &[gltf::buffer::Data],
+        objects: &mut Vec<GltfObject>,
+    ) -> anyhow::Result<()> {
+        let transform = parent_transform * from_columns(node.transform().matrix());
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                let base_color = primitive.material().pbr_metallic_roughness().base_color_factor();
+
+                let object = match load_primitive(&primitive, base_color, buffers)
+                    .with_context(|| format!("Unable to load mesh {}", mesh.name().unwrap_or("<unnamed>")))?
+                {
+                    Some(object) => object,
+                    None => continue,
+                };
+
+                objects.push(GltfObject {
+                    name: mesh.name().or(node.name()).map(str::to_string),
+                    object,
+                    transform: FLIP_Z * transform * FLIP_Z,
+                    base_color,
+                });
+            }
+        }
+
+        for child in node.children() {
+            load_node(&child, transform, buffers, objects)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_primitive(
+        primitive: &gltf::Primitive,
+        base_color: [f32; 4],
+        buffers: &[gltf::buffer::Data],
+    ) -> anyhow::Result<Option<Object>> {
+        let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| &data[..]));
+
+        let positions: Vec<Vec3> = reader.read_positions()
+            .context("Primitive has no positions")?
+            .map(|[x, y, z]| Vec3::from([x, y, -z]))
+            .collect();
+
+        let normals: Option<Vec<Vec3>> = reader.read_normals()
+            .map(|normals| normals.map(|[x, y, z]| Vec3::from([x, y, -z])).collect());
+
+        let uvs: Option<Vec<Vec2>> = reader.read_tex_coords(0)
+            .map(|uvs| uvs.into_f32().map(Vec2::from).collect());
+
+        let colors: Option<Vec<[f32; 4]>> = reader.read_colors(0)
+            .map(|colors| colors.into_rgba_f32().collect());
+
+        let indices: Vec<u32> = match reader.read_indices() {
+            Some(indices) => indices.into_u32().collect(),
+            None => (0..positions.len() as u32).collect(),
+        };
+
+        let corners = match primitive.mode() {
+            gltf::mesh::Mode::Triangles => indices.chunks_exact(3)
+                .map(|chunk| [chunk[0], chunk[1], chunk[2]])
+                .collect::<Vec<_>>(),
+            gltf::mesh::Mode::TriangleStrip => (2..indices.len())
+                .map(|i| match i % 2 {
+                    0 => [indices[i - 2], indices[i - 1], indices[i]],
+                    _ => [indices[i - 1], indices[i - 2], indices[i]],
+                })
+                .collect(),
+            gltf::mesh::Mode::TriangleFan => (2..indices.len())
+                .map(|i| [indices[0], indices[i - 1], indices[i]])
+                .collect(),
+            mode => {
+                log::warn!("Skipping primitive with unsupported mode {:?}", mode);
+                return Ok(None);
+            }
+        };
+
+        let vertex = |index: u32| -> anyhow::Result<Vertex> {
+            let index = index as usize;
+            let position = *positions.get(index)
+                .with_context(|| format!("Index {} out of range, {} vertices defined", index, positions.len()))?;
+
+            let mut color = base_color;
+            if let Some(vertex_color) = colors.as_ref().and_then(|colors| colors.get(index)) {
+                for (channel, factor) in color.iter_mut().zip(vertex_color) {
+                    *channel *= factor;
+                }
+            }
+
+            Ok(Vertex {
+                position,
+                color: Vec4::from(color),
+                uv: uvs.as_ref()
+                    .and_then(|uvs| uvs.get(index).copied())
+                    .unwrap_or(Vec2::from([0.0, 0.0])),
+                normal: normals.as_ref()
+                    .and_then(|normals| normals.get(index).copied())
+                    .filter(|normal| normal.magnitude() > f32::EPSILON)
+                    .map(Vec3::normalize)
+                    .unwrap_or(Vec3::from([0.0, 0.0, 0.0])),
+            })
+        };
+
+        let mut triangles = Vec::with_capacity(corners.len());
+
+        for [a, b, c] in corners {
+            let mut triangle = Triangle::new([vertex(a)?, vertex(b)?, vertex(c)?]);
+            let [p_1, p_2, p_3] = triangle.vertices.map(|vertex| vertex.position);
+
+            // Prefer the normals of the file, fall back to the winding of the face
+            let normal = match normals.as_ref() {
+                Some(normals) => [a, b, c].iter()
+                    .filter_map(|&i| normals.get(i as usize).copied())
+                    .fold(Vec3::from([0.0, 0.0, 0.0]), |sum, normal| sum + normal),
+                None => (p_2 - p_1) % (p_3 - p_1),
+            };
+
+            if normal.magnitude() > f32::EPSILON {
+                triangle.normal = normal.normalize();
+            }
+
+            triangles.push(triangle);
+        }
+
+        Ok(Some(Object::new([0.0, 0.0, 0.0], triangles, false)))
+    }
+
+    // glTF stores matrices column by column
+    fn from_columns(columns: [[f32; 4]; 4]) -> Mat4x4 {
+        Mat4x4::from(*columns.as_flattened().first_chunk::<16>().unwrap()).transpose()
+    }
+
+    fn transform_point(matrix: Mat4x4, point: Vec3) -> Vec3 {
+        let result = matrix * Vec4::from([point.x_1, point.x_2, point.x_3, 1.0]);
+
+        Vec3::from([result.x_1, result.x_2, result.x_3])
+    }
+}
@@ -0,0 +1,87 @@
+// Tracks which keys and mouse buttons are held down, so movement can be
+// applied once per frame instead of once per key (repeat) event.
+
+pub mod input {
+    use std::collections::HashSet;
+    use winit::event::MouseButton;
+    use winit::keyboard::KeyCode;
+
+    #[derive(Debug, Default)]
+    pub struct InputState {
+        keys: HashSet<KeyCode>,
+        buttons: HashSet<MouseButton>,
+        // Pressed since the last call to end_frame()
+        keys_pressed: HashSet<KeyCode>,
+        buttons_pressed: HashSet<MouseButton>,
+        // In physical pixels from the top left of the window, None outside of it
+        cursor: Option<[f32; 2]>,
+    }
+
+    impl InputState {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn set_key(&mut self, code: KeyCode, is_pressed: bool) {
+            if is_pressed {
+                // Key repeat events do not count as new presses
+                if self.keys.insert(code) {
+                    self.keys_pressed.insert(code);
+                }
+            } else {
+                self.keys.remove(&code);
+            }
+        }
+
+        pub fn set_button(&mut self, button: MouseButton, is_pressed: bool) {
+            if is_pressed {
+                if self.buttons.insert(button) {
+                    self.buttons_pressed.insert(button);
+                }
+            } else {
+                self.buttons.remove(&button);
+            }
+        }
+
+        pub fn set_cursor(&mut self, cursor: Option<[f32; 2]>) {
+            self.cursor = cursor;
+        }
+
+        pub fn cursor(&self) -> Option<[f32; 2]> {
+            self.cursor
+        }
+
+        pub fn is_key_held(&self, code: KeyCode) -> bool {
+            self.keys.contains(&code)
+        }
+
+        pub fn is_button_held(&self, button: MouseButton) -> bool {
+            self.buttons.contains(&button)
+        }
+
+        pub fn was_key_pressed(&self, code: KeyCode) -> bool {
+            self.keys_pressed.contains(&code)
+        }
+
+        pub fn was_button_pressed(&self, button: MouseButton) -> bool {
+            self.buttons_pressed.contains(&button)
+        }
+
+        // -1.0, 0.0 or 1.0 depending on which of the two keys is held.
+        pub fn axis(&self, negative: KeyCode, positive: KeyCode) -> f32 {
+            self.is_key_held(positive) as i32 as f32 - self.is_key_held(negative) as i32 as f32
+        }
+
+        // Releases everything, e.g. when the window loses focus and release events would be missed.
+        pub fn clear(&mut self) {
+            self.keys.clear();
+            self.buttons.clear();
+            self.end_frame();
+        }
+
+        pub fn end_frame(&mut self) {
+            self.keys_pressed.clear();
+            self.buttons_pressed.clear();
+        }
+    }
+}
@@ -1,3 +1,5 @@
+#![allow(clippy::module_inception)]
+
 use std::sync::Arc;
 use winit::{
     application::ApplicationHandler,
@@ -12,82 +14,118 @@ use wgpu::util::DeviceExt;
 
 use std::f32::consts::PI;
 
-mod camera;
-use camera::*;
-
-mod object;
-use object::*;
+pub mod camera;
+pub mod character;
+pub mod collision;
+pub mod fps;
+pub mod gltf_loader;
+pub mod input;
+pub mod light;
+pub mod material;
+pub mod mesh;
+pub mod obj_loader;
+pub mod object;
+pub mod overlay;
+pub mod physics;
+pub mod picking;
+pub mod rasterizer;
+pub mod raycast;
+pub mod scene;
+pub mod texture;
+pub mod timestep;
 
 // Consts
-const TWO_PI: f32 = 2.0 * PI;
 const HALF_PI: f32 = 0.5 * PI;
+// Mixed over the selected object
+const SELECTION_HIGHLIGHT: [f32; 4] = [1.0, 0.8, 0.2, 0.35];
+// Orbit drags that stay within this many pixels still count as clicks
+const CLICK_DISTANCE: f32 = 4.0;
 
 pub struct State {
-    surface: wgpu::Surface<'static>,
+    // Both are None when rendering headless
+    surface: Option<wgpu::Surface<'static>>,
+    window: Option<Arc<Window>>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     is_surface_configured: bool,
-    window: Arc<Window>,
-    render_pipeline: wgpu::RenderPipeline,
-    vertex_buffer: wgpu::Buffer,
-    vertices: Vec<Vertex>,
+    render_pipelines: RenderPipelines,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    shader: wgpu::ShaderModule,
+    depth_settings: DepthSettings,
+    depth_texture: texture::texture::Texture,
+    meshes: mesh::mesh::MeshRegistry,
+    textures: texture::texture::TextureRegistry,
+    materials: material::material::MaterialRegistry,
+    scene: scene::scene::SceneGraph,
+    collisions: collision::collision::CollisionWorld,
+    physics: physics::physics::PhysicsWorld,
     camera: camera::camera::Camera,
-    camera_matrix: [[f32; 3]; 3],
-    delta_time: std::time::Instant,
+    controller: camera::camera::CameraController,
+    input: input::input::InputState,
+    cursor_grabbed: bool,
+    pick_mode: picking::picking::PickMode,
+    // Created on the first pick in PickMode::IdBuffer
+    id_buffer: Option<picking::picking::IdBuffer>,
+    selected: Option<mesh::mesh::MeshId>,
+    // Where the left button went down, to tell clicks from orbit drags
+    click_start: Option<[f32; 2]>,
+    timestep: timestep::timestep::FixedTimestep,
+    fixed_update: Option<FixedUpdate>,
+    // Distance the camera moved during the last tick, to interpolate between ticks
+    camera_motion: [f32; 3],
     frame_times: FrameTimes,
-    mouse_sensitivity: f32,
+    fps_counter: fps::fps::FpsCounter,
+    overlay: overlay::overlay::Overlay,
     camera_buffer: wgpu::Buffer,
     camera_uniform: camera::camera::CameraUniform,
     camera_bind_group: wgpu::BindGroup,
+    lights: light::light::Lights,
+    light_buffer: wgpu::Buffer,
+    light_bind_group: wgpu::BindGroup,
 }
 
 struct FrameTimes {
-    delta_time: std::time::Instant,
-    delta_time_sum: std::time::Duration,
-    update_time: std::time::Duration,
-    sample_size: u32,
-}
-
-impl FrameTimes {
-    fn reset(&mut self) {
-        self.delta_time = std::time::Instant::now();
-        self.delta_time_sum = std::time::Duration::from_secs(0);
-        self.sample_size = 1;
-    }
-
-    fn new_sample(mut self) -> Self {
-        self.delta_time = std::time::Instant::now();
-        self.sample_size = self.sample_size + 1;
-        
-        self
-    }
+    last_update: std::time::Instant,
+    // Of the frame in progress, in seconds
+    frame_time: f32,
+    update_time: f32,
 }
 
-
-
-pub struct FpsCounter {}
-
+type Setup = Box<dyn FnOnce(&mut State)>;
+// Called once per simulation tick with the tick length in seconds
+pub type FixedUpdate = Box<dyn FnMut(&mut State, f32)>;
 
 pub struct App {
     state: Option<State>,
+    setup: Option<Setup>,
 }
 
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct Vertex {
     position: [f32; 3],
-    color: [f32; 3],
-    camera_position: [f32; 3],
-    camera_matrix: [[f32; 3]; 3],
-    scale_factor: f32,
+    color: [f32; 4],
     normal: [f32; 3],
-    light_source: [f32; 3],
+    uv: [f32; 2],
 }
 
 impl Vertex {
+    fn from_object(
+        position: object::object::gmlib::matrix::Vec3,
+        color: object::object::gmlib::matrix::Vec4,
+        normal: object::object::gmlib::matrix::Vec3,
+        uv: object::object::gmlib::matrix::Vec2,
+    ) -> Self {
+        Self {
+            position: position.to_array(),
+            color: color.to_array(),
+            normal: normal.to_array(),
+            uv: uv.to_array(),
+        }
+    }
+
     fn descriptor() -> wgpu::VertexBufferLayout<'static> {
-        use std::mem;
         use wgpu::{
             VertexAttribute,
             BufferAddress,
@@ -108,49 +146,238 @@ impl Vertex {
                 VertexAttribute {
                     offset: size_of::<[f32; 3]>() as BufferAddress,
                     shader_location: 1,
-                    format: VertexFormat::Float32x3,
+                    format: VertexFormat::Float32x4,
                 },
                 VertexAttribute {
-                    offset: size_of::<[f32; 6]>() as BufferAddress,
+                    offset: size_of::<[f32; 7]>() as BufferAddress,
                     shader_location: 2,
                     format: VertexFormat::Float32x3,
                 },
                 VertexAttribute {
-                    offset: size_of::<[f32; 9]>() as BufferAddress,
+                    offset: size_of::<[f32; 10]>() as BufferAddress,
                     shader_location: 3,
-                    format: VertexFormat::Float32x3,
-                },
-                VertexAttribute {
-                    offset: size_of::<[f32; 12]>() as BufferAddress,
-                    shader_location: 4,
-                    format: VertexFormat::Float32x3,
-                },
-                VertexAttribute {
-                    offset: size_of::<[f32; 15]>() as BufferAddress,
-                    shader_location: 5,
-                    format: VertexFormat::Float32x3,
-                },
-                VertexAttribute {
-                    offset: size_of::<[f32; 18]>() as BufferAddress,
-                    shader_location: 6,
-                    format: VertexFormat::Float32,
-                },
-                VertexAttribute {
-                    offset: size_of::<[f32; 19]>() as BufferAddress,
-                    shader_location: 7,
-                    format: VertexFormat::Float32x3,
-                },
-                VertexAttribute {
-                    offset: size_of::<[f32; 22]>() as BufferAddress,
-                    shader_location: 8,
-                    format: VertexFormat::Float32x3,
+                    format: VertexFormat::Float32x2,
                 },
             ]
         }
     }
 }
 
-const BACKGROUND_COLOR: wgpu::Color = wgpu::Color {
+// Per instance data, the matrices are stored as columns like WGSL expects
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct Instance {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+    tint: [f32; 4],
+}
+
+impl Instance {
+    fn from_mesh(instance: &mesh::mesh::Instance) -> Self {
+        Self {
+            model: instance.transform.transpose().to_array(),
+            normal: instance.transform.normal_matrix().transpose().to_array(),
+            tint: instance.tint,
+        }
+    }
+
+    // Meshes without instances are drawn once with this one
+    const IDENTITY: Self = Self {
+        model: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+        normal: [
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 1.0],
+        ],
+        tint: [1.0, 1.0, 1.0, 1.0],
+    };
+
+    fn descriptor() -> wgpu::VertexBufferLayout<'static> {
+        use wgpu::{
+            VertexAttribute,
+            BufferAddress,
+            VertexFormat,
+            VertexStepMode,
+            VertexBufferLayout,
+        };
+
+        const ATTRIBUTES: [VertexAttribute; 8] = [
+            // Model matrix columns
+            VertexAttribute {
+                offset: 0,
+                shader_location: 4,
+                format: VertexFormat::Float32x4,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 4]>() as BufferAddress,
+                shader_location: 5,
+                format: VertexFormat::Float32x4,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 8]>() as BufferAddress,
+                shader_location: 6,
+                format: VertexFormat::Float32x4,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 12]>() as BufferAddress,
+                shader_location: 7,
+                format: VertexFormat::Float32x4,
+            },
+            // Normal matrix columns
+            VertexAttribute {
+                offset: size_of::<[f32; 16]>() as BufferAddress,
+                shader_location: 8,
+                format: VertexFormat::Float32x3,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 19]>() as BufferAddress,
+                shader_location: 9,
+                format: VertexFormat::Float32x3,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 22]>() as BufferAddress,
+                shader_location: 10,
+                format: VertexFormat::Float32x3,
+            },
+            VertexAttribute {
+                offset: size_of::<[f32; 25]>() as BufferAddress,
+                shader_location: 11,
+                format: VertexFormat::Float32x4,
+            },
+        ];
+
+        VertexBufferLayout {
+            array_stride: size_of::<Instance>() as BufferAddress,
+            step_mode: VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct DepthSettings {
+    pub compare: wgpu::CompareFunction,
+    pub write_enabled: bool,
+}
+
+impl Default for DepthSettings {
+    fn default() -> Self {
+        Self {
+            compare: wgpu::CompareFunction::Less,
+            write_enabled: true,
+        }
+    }
+}
+
+// Blended pipelines never write depth, double-sided ones do not cull back faces
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    shader: &wgpu::ShaderModule,
+    color_format: wgpu::TextureFormat,
+    depth_settings: DepthSettings,
+    blend: bool,
+    double_sided: bool,
+) -> wgpu::RenderPipeline {
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Render pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: Some("vs_main"),
+            buffers: &[
+                Vertex::descriptor(),
+                Instance::descriptor(),
+            ],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: Some("fs_main"),
+            targets: &[Some(wgpu::ColorTargetState {
+                format: color_format,
+                blend: Some(if blend { wgpu::BlendState::ALPHA_BLENDING } else { wgpu::BlendState::REPLACE }),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: if double_sided { None } else { Some(wgpu::Face::Back) },
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: depth_settings.write_enabled && !blend,
+            depth_compare: depth_settings.compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+// One pipeline per combination of alpha and culling mode, masked materials
+// discard in the shader and share the opaque pipelines.
+struct RenderPipelines {
+    opaque: wgpu::RenderPipeline,
+    opaque_double_sided: wgpu::RenderPipeline,
+    blend: wgpu::RenderPipeline,
+    blend_double_sided: wgpu::RenderPipeline,
+}
+
+impl RenderPipelines {
+    fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::PipelineLayout,
+        shader: &wgpu::ShaderModule,
+        color_format: wgpu::TextureFormat,
+        depth_settings: DepthSettings,
+    ) -> Self {
+        let create = |blend, double_sided| create_render_pipeline(
+            device,
+            layout,
+            shader,
+            color_format,
+            depth_settings,
+            blend,
+            double_sided,
+        );
+
+        Self {
+            opaque: create(false, false),
+            opaque_double_sided: create(false, true),
+            blend: create(true, false),
+            blend_double_sided: create(true, true),
+        }
+    }
+
+    fn get(&self, material: &material::material::Material) -> &wgpu::RenderPipeline {
+        match (material.is_blended(), material.double_sided) {
+            (false, false) => &self.opaque,
+            (false, true) => &self.opaque_double_sided,
+            (true, false) => &self.blend,
+            (true, true) => &self.blend_double_sided,
+        }
+    }
+}
+
+pub const BACKGROUND_COLOR: wgpu::Color = wgpu::Color {
     r: 0.10,
     g: 0.60,
     b: 1.00,
@@ -159,8 +386,6 @@ const BACKGROUND_COLOR: wgpu::Color = wgpu::Color {
 
 impl State {
     pub async fn new(window: Arc<Window>) -> anyhow::Result<Self> {
-        use std::f32::consts::PI;
-
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
@@ -203,16 +428,83 @@ impl State {
             desired_maximum_frame_latency: 2,
         };
 
+        if let Err(e) = window.set_cursor_grab(winit::window::CursorGrabMode::Locked) {
+            log::warn!("Unable to grab cursor {}", e);
+        }
+
+        Ok(Self::from_device(device, queue, config, Some(surface), Some(window)))
+    }
+
+    // Renders into an offscreen texture instead of a window, use render_image() to read frames back.
+    // Any adapter will do, including a software fallback adapter on machines without a GPU.
+    pub async fn new_headless(width: u32, height: u32) -> anyhow::Result<Self> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let adapter = match instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            compatible_surface: None,
+            force_fallback_adapter: false,
+        }).await {
+            Ok(adapter) => adapter,
+            Err(_) => instance.request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::default(),
+                compatible_surface: None,
+                force_fallback_adapter: true,
+            }).await?,
+        };
+
+        let (device, queue) = adapter.request_device(&wgpu::DeviceDescriptor {
+            label: None,
+            required_features: wgpu::Features::empty(),
+            experimental_features: wgpu::ExperimentalFeatures::disabled(),
+            required_limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+            memory_hints: Default::default(),
+            trace: wgpu::Trace::Off,
+        }).await?;
+
+        // There is no surface, the configuration only carries the size and format of the frames
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Immediate,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+
+        let mut state = Self::from_device(device, queue, config, None, None);
+        state.is_surface_configured = true;
+
+        Ok(state)
+    }
+
+    fn from_device(
+        device: wgpu::Device,
+        queue: wgpu::Queue,
+        config: wgpu::SurfaceConfiguration,
+        surface: Option<wgpu::Surface<'static>>,
+        window: Option<Arc<Window>>,
+    ) -> Self {
         let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
             label: Some("Shader"),
             source: wgpu::ShaderSource::Wgsl(include_str!("./shader.wgsl").into()),
         });
 
-        let camera = camera::camera::Camera::new([0.0, 0.0, 0.0], 0.0, 0.0, 0.005, 1.0);
-
-        let camera_matrix = camera.matrix();
+        let mut camera = camera::camera::Camera::new(
+            [0.0, 0.0, 0.0],
+            0.0,
+            0.0,
+            0.005,
+            camera::camera::Projection::default(),
+        );
+        camera.update_aspect_ratio(&config);
 
-        let mut camera_uniform = camera::camera::CameraUniform::new(camera);
+        let camera_uniform = camera::camera::CameraUniform::new(camera);
 
         let camera_buffer = device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
@@ -227,7 +519,7 @@ impl State {
                 entries: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStages::VERTEX,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
                         ty: wgpu::BindingType::Buffer {
                             ty: wgpu::BufferBindingType::Uniform,
                             has_dynamic_offset: false,
@@ -251,270 +543,991 @@ impl State {
             label: Some("Camera bind group"),
         });
 
+        let lights = light::light::Lights::new();
+
+        let light_buffer = device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light buffer"),
+                contents: bytemuck::cast_slice(&[lights.uniform()]),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let light_bind_group_layout = device.create_bind_group_layout(
+            &wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }
+                ],
+                label: Some("Light bind group layout"),
+            }
+        );
+
+        let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &light_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("Light bind group"),
+        });
+
+        let meshes = mesh::mesh::MeshRegistry::new(&device);
+        let textures = texture::texture::TextureRegistry::new(&device, &queue);
+        let materials = material::material::MaterialRegistry::new(&device);
+
         let render_pipeline_layout = device.create_pipeline_layout(
             &wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
                     &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    meshes.bind_group_layout(),
+                    materials.bind_group_layout(),
                 ],
                 push_constant_ranges: &[],
             }
         );
 
-        println!("{}", size_of::<camera::camera::CameraUniform>());
-        
-        let scale_factor = 1.25;
-        let normal = [0.0, 0.0, 0.0];
-        let light_source = [-1.5, 0.0, 1.0];
-
-        let vertices = vec!(
-            // Tetrahedron
-            Vertex { position: [-2.0, -0.35, 2.0], color: [0.9, 0.0, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal: [0.0, 0.0, 1.0], light_source },
-            Vertex { position: [-1.5, 0.0, 2.0], color: [0.9, 0.0, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal: [0.0, 0.0, 1.0], light_source },
-            Vertex { position: [-1.8, 0.6, 2.0], color: [0.9, 0.0, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal: [0.0, 0.0, 1.0], light_source },
-
-            Vertex { position: [-1.5, 0.0, 2.0], color: [0.0, 0.8, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-2.0, -0.35, 2.0], color: [0.0, 0.8, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-1.75, 2.4, 8.0], color: [0.0, 0.6, 0.0], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            
-            Vertex { position: [-1.5, 0.0, 2.0], color: [0.0, 0.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-1.75, 2.4, 8.0], color: [0.0, 0.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-1.8, 0.6, 2.0], color: [0.0, 0.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [-2.0, -0.35, 2.0], color: [0.1, 0.1, 0.8], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-1.8, 0.6, 2.0], color: [0.1, 0.1, 0.8], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [-1.75, 2.4, 8.0], color: [0.1, 0.1, 0.8], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 0.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 1.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, 1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 1.0, 1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [0.0, 1.0, 1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 1.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 0.0, 1.0], color: [0.5, 1.5, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, -1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [0.0, 1.0, -1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [200.0, 1.0, -1.0], color: [0.1, 1.1, 0.7], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, 2.0], color: [0.3, 0.1, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [1.0, 1.0, 2.0], color: [0.3, 0.1, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [0.0, 1.0, 3.0], color: [0.3, 0.1, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-
-            Vertex { position: [0.0, 0.0, 3.0], color: [0.0, 0.7, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [1.0, 1.0, 3.0], color: [0.0, 0.7, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-            Vertex { position: [0.0, 1.0, 4.0], color: [0.0, 0.7, 0.1], camera_position: camera.position, camera_matrix, scale_factor, normal, light_source },
-        );
-
+        let depth_settings = DepthSettings::default();
 
-        let vertex_buffer = device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
+        let render_pipelines = RenderPipelines::new(
+            &device,
+            &render_pipeline_layout,
+            &shader,
+            config.format,
+            depth_settings,
         );
 
-        let render_pipeline =
-            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-                label: Some("Render pipeline"),
-                layout: Some(&render_pipeline_layout), vertex: wgpu::VertexState {
-                    module: &shader,
-                    entry_point: Some("vs_main"),
-                    buffers: &[
-                        Vertex::descriptor(),
-                    ],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                },
-                fragment: Some(wgpu::FragmentState {
-                    module: &shader,
-                    entry_point: Some("fs_main"),
-                    targets: &[Some(wgpu::ColorTargetState {
-                        format: config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
-                        write_mask: wgpu::ColorWrites::ALL,
-                    })],
-                    compilation_options: wgpu::PipelineCompilationOptions::default(),
-                }),
-                primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
-                    polygon_mode: wgpu::PolygonMode::Fill,
-                    unclipped_depth: false,
-                    conservative: false,
-                },
-                depth_stencil: None,
-                multisample: wgpu::MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-                cache: None,
-            });
-
-        let delta_time = std::time::Instant::now();
+        let depth_texture = texture::texture::Texture::create_depth_texture(&device, &config, "Depth texture");
 
         let frame_times = FrameTimes {
-            delta_time: std::time::Instant::now(),
-            delta_time_sum: std::time::Duration::from_secs(0),
-            update_time: std::time::Duration::from_secs(1),
-            sample_size: 1,
+            last_update: std::time::Instant::now(),
+            frame_time: 0.0,
+            update_time: 0.0,
         };
 
-        window.set_cursor_grab(winit::window::CursorGrabMode::Locked);
-
-        let mouse_sensitivity = 0.01;
+        let overlay = overlay::overlay::Overlay::new(&device, config.format);
+        // State::new grabs it
+        let cursor_grabbed = window.is_some();
 
-        Ok(Self {
+        Self {
             surface,
+            window,
             device,
             queue,
             config,
             is_surface_configured: false,
-            window,
-            render_pipeline,
-            vertex_buffer,
-            vertices,
+            render_pipelines,
+            render_pipeline_layout,
+            shader,
+            depth_settings,
+            depth_texture,
+            meshes,
+            textures,
+            materials,
+            scene: scene::scene::SceneGraph::new(),
+            collisions: collision::collision::CollisionWorld::new(),
+            physics: physics::physics::PhysicsWorld::new(),
             camera,
-            camera_matrix,
-            delta_time,
+            controller: camera::camera::CameraController::FirstPerson,
+            input: input::input::InputState::new(),
+            cursor_grabbed,
+            pick_mode: picking::picking::PickMode::default(),
+            id_buffer: None,
+            selected: None,
+            click_start: None,
+            timestep: timestep::timestep::FixedTimestep::default(),
+            fixed_update: None,
+            camera_motion: [0.0, 0.0, 0.0],
             frame_times,
-            mouse_sensitivity,
+            fps_counter: fps::fps::FpsCounter::default(),
+            overlay,
             camera_buffer,
             camera_uniform,
             camera_bind_group,
-        })
+            lights,
+            light_buffer,
+            light_bind_group,
+        }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
         if width > 0 && height > 0 {
             self.config.width = width;
             self.config.height = height;
-            self.surface.configure(&self.device, &self.config);
+            if let Some(surface) = &self.surface {
+                surface.configure(&self.device, &self.config);
+            }
+            self.depth_texture = texture::texture::Texture::create_depth_texture(&self.device, &self.config, "Depth texture");
+            self.camera.update_aspect_ratio(&self.config);
             self.is_surface_configured = true;
         }
     }
 
+    pub fn add_object(&mut self, object: object::object::Object) -> mesh::mesh::MeshId {
+        self.meshes.add(&self.device, object)
+    }
+
+    pub fn remove_object(&mut self, id: mesh::mesh::MeshId) -> Option<object::object::Object> {
+        if self.selected == Some(id) {
+            self.selected = None;
+        }
+        self.physics.remove(id);
+        self.meshes.remove(id)
+    }
+
+    // Vertices of objects added afterwards are welded when their positions are at most
+    // this far apart, 0 only welds identical vertices.
+    pub fn set_weld_tolerance(&mut self, weld_tolerance: f32) {
+        self.meshes.set_weld_tolerance(weld_tolerance);
+    }
+
+    // Draws another copy of an object, placed by the instance transform relative to the
+    // object. Once an object has instances only the instances are drawn, and only
+    // the instances collide. None if the object does not exist.
+    pub fn add_instance(&mut self, id: mesh::mesh::MeshId, instance: mesh::mesh::Instance) -> Option<mesh::mesh::InstanceId> {
+        self.meshes.add_instance(id, instance)
+    }
+
+    pub fn update_instance(&mut self, id: mesh::mesh::MeshId, instance_id: mesh::mesh::InstanceId, instance: mesh::mesh::Instance) -> bool {
+        self.meshes.update_instance(id, instance_id, instance)
+    }
+
+    pub fn remove_instance(&mut self, id: mesh::mesh::MeshId, instance_id: mesh::mesh::InstanceId) -> Option<mesh::mesh::Instance> {
+        self.meshes.remove_instance(id, instance_id)
+    }
+
+    // Reads a PNG, BMP or TGA file into a texture with mipmaps
+    pub fn load_texture(
+        &mut self,
+        path: impl AsRef<std::path::Path>,
+        sampler: texture::texture::SamplerSettings,
+    ) -> anyhow::Result<texture::texture::TextureId> {
+        self.textures.load(&self.device, &self.queue, path, sampler)
+    }
+
+    // Fails for images larger than the device supports
+    pub fn add_texture(
+        &mut self,
+        image: &image::DynamicImage,
+        sampler: texture::texture::SamplerSettings,
+    ) -> anyhow::Result<texture::texture::TextureId> {
+        let texture = texture::texture::Texture::from_image(&self.device, &self.queue, image, "Texture", sampler)?;
+        Ok(self.textures.add(texture, sampler))
+    }
+
+    // Materials still using the texture are drawn untextured
+    pub fn remove_texture(&mut self, id: texture::texture::TextureId) -> Option<texture::texture::Texture> {
+        self.textures.remove(id)
+    }
+
+    pub fn set_sampler(&mut self, id: texture::texture::TextureId, sampler: texture::texture::SamplerSettings) -> bool {
+        self.textures.set_sampler(&self.device, id, sampler)
+    }
+
+    pub fn add_material(&mut self, material: material::material::Material) -> material::material::MaterialId {
+        self.materials.add(material)
+    }
+
+    pub fn update_material(&mut self, id: material::material::MaterialId, material: material::material::Material) -> bool {
+        self.materials.update(id, material)
+    }
+
+    // Objects still using the material are drawn with the default material
+    pub fn remove_material(&mut self, id: material::material::MaterialId) -> Option<material::material::Material> {
+        self.materials.remove(id)
+    }
+
+    pub fn material(&self, id: material::material::MaterialId) -> Option<&material::material::Material> {
+        self.materials.get(id)
+    }
+
+    // Used by objects without a material
+    pub fn set_default_material(&mut self, material: material::material::Material) {
+        self.materials.set_default_material(material);
+    }
+
+    // None uses the default material
+    pub fn set_material(&mut self, id: mesh::mesh::MeshId, material: Option<material::material::MaterialId>) -> bool {
+        self.meshes.set_material(id, material)
+    }
+
+    pub fn object(&self, id: mesh::mesh::MeshId) -> Option<&object::object::Object> {
+        self.meshes.get(id).map(|mesh| &mesh.object)
+    }
+
+    pub fn objects(&self) -> impl Iterator<Item = &object::object::Object> {
+        self.meshes.iter().map(|(_, mesh)| &mesh.object)
+    }
+
+    // Re-uploads the geometry of an object, returns the previous version.
+    pub fn replace_object(&mut self, id: mesh::mesh::MeshId, object: object::object::Object) -> Option<object::object::Object> {
+        self.collisions.invalidate(id);
+        self.meshes.replace(&self.device, id, object)
+    }
+
+    // Adds a scene graph node below parent, or a root node. None if the parent does not exist.
+    pub fn add_node(&mut self, parent: Option<scene::scene::NodeId>, transform: scene::scene::Transform) -> Option<scene::scene::NodeId> {
+        self.scene.add(parent, transform)
+    }
+
+    // Removes the node with all of its descendants and the objects attached to them.
+    pub fn remove_node(&mut self, id: scene::scene::NodeId) -> Vec<object::object::Object> {
+        self.scene.remove(id)
+            .into_iter()
+            .filter_map(|mesh| self.remove_object(mesh))
+            .collect()
+    }
+
+    // Adds an object that moves with the node, None if the node does not exist.
+    pub fn attach_object(&mut self, node: scene::scene::NodeId, object: object::object::Object) -> Option<mesh::mesh::MeshId> {
+        self.scene.get(node)?;
+
+        let mesh = self.meshes.add(&self.device, object);
+        self.scene.attach_mesh(node, mesh);
+
+        Some(mesh)
+    }
+
+    pub fn node(&self, id: scene::scene::NodeId) -> Option<&scene::scene::Node> {
+        self.scene.get(id)
+    }
+
+    pub fn set_node_transform(&mut self, id: scene::scene::NodeId, transform: scene::scene::Transform) {
+        self.scene.set_transform(id, transform);
+    }
+
+    pub fn node_transform_mut(&mut self, id: scene::scene::NodeId) -> Option<&mut scene::scene::Transform> {
+        self.scene.transform_mut(id)
+    }
+
+    // False if either node does not exist or the node would become its own ancestor.
+    pub fn set_node_parent(&mut self, id: scene::scene::NodeId, parent: Option<scene::scene::NodeId>) -> bool {
+        self.scene.set_parent(id, parent)
+    }
+
+    pub fn scene(&self) -> &scene::scene::SceneGraph {
+        &self.scene
+    }
+
+    pub fn camera(&self) -> &camera::camera::Camera {
+        &self.camera
+    }
+
+    pub fn camera_mut(&mut self) -> &mut camera::camera::Camera {
+        &mut self.camera
+    }
+
+    pub fn set_projection(&mut self, projection: camera::camera::Projection) {
+        self.camera.projection = projection;
+    }
+
+    pub fn set_depth_settings(&mut self, depth_settings: DepthSettings) {
+        self.depth_settings = depth_settings;
+        self.render_pipelines = RenderPipelines::new(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            self.config.format,
+            self.depth_settings,
+        );
+        // Recreated with the new settings on the next pick
+        self.id_buffer = None;
+    }
+
+    // Returns None once MAX_LIGHTS lights exist.
+    pub fn add_light(&mut self, light: light::light::Light) -> Option<light::light::LightId> {
+        self.lights.add(light)
+    }
+
+    pub fn remove_light(&mut self, id: light::light::LightId) -> Option<light::light::Light> {
+        self.lights.remove(id)
+    }
+
+    pub fn move_light(&mut self, id: light::light::LightId, position: [f32; 3]) {
+        if let Some(light) = self.lights.get_mut(id) {
+            light.position = position;
+        }
+    }
+
+    pub fn light(&self, id: light::light::LightId) -> Option<&light::light::Light> {
+        self.lights.get(id)
+    }
+
+    pub fn light_mut(&mut self, id: light::light::LightId) -> Option<&mut light::light::Light> {
+        self.lights.get_mut(id)
+    }
+
+    pub fn set_ambient_light(&mut self, color: [f32; 3]) {
+        self.lights.set_ambient(color);
+    }
+
+    pub fn timestep(&self) -> &timestep::timestep::FixedTimestep {
+        &self.timestep
+    }
+
+    pub fn set_tick_rate(&mut self, tick_rate: f32) {
+        self.timestep.set_tick_rate(tick_rate);
+    }
+
+    pub fn set_max_steps(&mut self, max_steps: u32) {
+        self.timestep.set_max_steps(max_steps);
+    }
+
+    // Gameplay code that has to run at the fixed tick rate, replaces any previous callback.
+    pub fn on_fixed_update(&mut self, fixed_update: impl FnMut(&mut State, f32) + 'static) {
+        self.fixed_update = Some(Box::new(fixed_update));
+    }
+
+    pub fn fps_counter(&self) -> &fps::fps::FpsCounter {
+        &self.fps_counter
+    }
+
+    pub fn frame_stats(&self) -> fps::fps::FrameStats {
+        self.fps_counter.stats()
+    }
+
+    // The overlay can also be toggled with F3.
+    pub fn set_overlay_visible(&mut self, visible: bool) {
+        self.overlay.visible = visible;
+    }
+
+    pub fn is_overlay_visible(&self) -> bool {
+        self.overlay.visible
+    }
+
+    // Colliders of the objects flagged with `collision`, updated every tick
+    pub fn collisions(&self) -> &collision::collision::CollisionWorld {
+        &self.collisions
+    }
+
+    // Pairs of objects that touched during the last tick
+    pub fn colliding_pairs(&self) -> &[collision::collision::CollisionPair] {
+        self.collisions.pairs()
+    }
+
+    // Contacts of a shape with the colliders as of the last tick
+    pub fn query_collisions(&self, shape: &collision::collision::Shape) -> Vec<(mesh::mesh::MeshId, collision::collision::Contact)> {
+        self.collisions.query(shape)
+    }
+
+    // Closest triangle of any object along the ray, direction does not have to be normalized
+    pub fn raycast(&self, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<raycast::raycast::RaycastHit<'_>> {
+        self.raycast_filtered(origin, direction, max_distance, |_, _| true)
+    }
+
+    // Skips the objects filter returns false for, like the one casting the ray
+    pub fn raycast_filtered(
+        &self,
+        origin: [f32; 3],
+        direction: [f32; 3],
+        max_distance: f32,
+        filter: impl Fn(mesh::mesh::MeshId, &object::object::Object) -> bool,
+    ) -> Option<raycast::raycast::RaycastHit<'_>> {
+        let ray = object::object::gmlib::matrix::Ray::new(
+            object::object::gmlib::matrix::Vec3::from(origin),
+            object::object::gmlib::matrix::Vec3::from(direction),
+        );
+        raycast::raycast::raycast_filtered(&self.meshes, ray, max_distance, filter)
+    }
+
+    // Camera ray through a pixel of the window, in physical pixels from the top left
+    pub fn screen_ray(&self, pixel: [f32; 2]) -> object::object::gmlib::matrix::Ray {
+        let ndc = [
+            2.0 * pixel[0] / self.config.width.max(1) as f32 - 1.0,
+            1.0 - 2.0 * pixel[1] / self.config.height.max(1) as f32,
+        ];
+
+        self.camera.ray(ndc)
+    }
+
+    // The cursor while it is free, the center of the window while it is grabbed
+    pub fn pick_position(&self) -> [f32; 2] {
+        let center = [0.5 * self.config.width as f32, 0.5 * self.config.height as f32];
+
+        match self.input.cursor() {
+            Some(cursor) if !self.cursor_grabbed => cursor,
+            _ => center,
+        }
+    }
+
+    pub fn pick_mode(&self) -> picking::picking::PickMode {
+        self.pick_mode
+    }
+
+    pub fn set_pick_mode(&mut self, pick_mode: picking::picking::PickMode) {
+        self.pick_mode = pick_mode;
+    }
+
+    // The object under pick_position()
+    pub fn pick(&mut self) -> Option<mesh::mesh::MeshId> {
+        self.pick_at(self.pick_position())
+    }
+
+    pub fn pick_at(&mut self, pixel: [f32; 2]) -> Option<mesh::mesh::MeshId> {
+        match self.pick_mode {
+            picking::picking::PickMode::Ray => {
+                let ray = self.screen_ray(pixel);
+                raycast::raycast::raycast(&self.meshes, ray, f32::INFINITY).map(|hit| hit.mesh)
+            }
+            picking::picking::PickMode::IdBuffer => {
+                // The ID buffer has to see what the last frame drew
+                self.update_uniforms();
+
+                let id_buffer = self.id_buffer.get_or_insert_with(|| picking::picking::IdBuffer::new(
+                    &self.device,
+                    &self.render_pipeline_layout,
+                    &self.shader,
+                    self.depth_settings,
+                ));
+
+                let pixel = pixel.map(|value| value.max(0.0) as u32);
+                let eye = object::object::gmlib::matrix::Vec3::from(self.camera.position);
+                let pick_id = id_buffer.read(&self.device, &self.queue, &self.config, pixel, |render_pass, pipelines| {
+                    render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+                    render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+                    self.meshes.draw(render_pass, &self.materials, eye, |material| pipelines.get(material));
+                });
+
+                match pick_id {
+                    Ok(pick_id) => self.meshes.from_pick_id(pick_id),
+                    Err(e) => {
+                        log::error!("Unable to read the ID buffer {}", e);
+                        None
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn selected(&self) -> Option<mesh::mesh::MeshId> {
+        self.selected
+    }
+
+    // Highlights the object, None clears the selection
+    pub fn select(&mut self, id: Option<mesh::mesh::MeshId>) {
+        if let Some(previous) = self.selected.take() {
+            self.meshes.set_highlight(previous, [0.0; 4]);
+        }
+
+        if let Some(id) = id.filter(|id| self.meshes.get(*id).is_some()) {
+            self.meshes.set_highlight(id, SELECTION_HIGHLIGHT);
+            self.selected = Some(id);
+        }
+    }
+
+    fn handle_click(&mut self) {
+        let picked = self.pick();
+
+        match picked.and_then(|id| self.object(id).map(|object| (id, object))) {
+            Some((id, object)) => log::info!(
+                "Selected object {:?} at {:?} with {} triangles",
+                id,
+                object.position.to_array(),
+                object.triangles.len(),
+            ),
+            None => log::info!("Selection cleared"),
+        }
+
+        self.select(picked);
+    }
+
+    // Makes the object a dynamic body starting where its mesh is. Its mass properties
+    // come from the triangles scaled like the mesh, which have to form a closed mesh,
+    // false otherwise.
+    // Bodies only collide when the object is flagged with `collision`.
+    pub fn add_rigid_body(&mut self, id: mesh::mesh::MeshId, density: f32) -> bool {
+        let mesh = match self.meshes.get(id) {
+            Some(mesh) => mesh,
+            None => return false,
+        };
+
+        let scale = physics::physics::RigidBody::scale_of(mesh.transform());
+        match physics::physics::RigidBody::from_scaled_object(&mesh.object, density, scale) {
+            Some(mut body) => {
+                body.set_transform(mesh.transform());
+                self.physics.add(id, body);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The mesh stays where the body left it
+    pub fn remove_rigid_body(&mut self, id: mesh::mesh::MeshId) -> Option<physics::physics::RigidBody> {
+        self.physics.remove(id)
+    }
+
+    pub fn rigid_body(&self, id: mesh::mesh::MeshId) -> Option<&physics::physics::RigidBody> {
+        self.physics.get(id)
+    }
+
+    pub fn rigid_body_mut(&mut self, id: mesh::mesh::MeshId) -> Option<&mut physics::physics::RigidBody> {
+        self.physics.get_mut(id)
+    }
+
+    pub fn physics(&self) -> &physics::physics::PhysicsWorld {
+        &self.physics
+    }
+
+    pub fn physics_mut(&mut self) -> &mut physics::physics::PhysicsWorld {
+        &mut self.physics
+    }
+
+    pub fn input(&self) -> &input::input::InputState {
+        &self.input
+    }
+
+    // Movement keys are only recorded here, the camera moves in update().
     pub fn handle_key(&mut self, event_loop: &ActiveEventLoop, code: KeyCode, is_pressed: bool) {
-        let movement_direction = self.camera.direction();
-        let increment = 0.05;
+        let is_repeat = is_pressed && self.input.is_key_held(code);
+        self.input.set_key(code, is_pressed);
+
+        if is_repeat {
+            return;
+        }
 
         match (code, is_pressed) {
             (KeyCode::Escape, true) => event_loop.exit(),
+            (KeyCode::F3, true) => self.overlay.visible = !self.overlay.visible,
+            (KeyCode::Tab, true) => self.set_controller(match self.controller {
+                camera::camera::CameraController::FirstPerson
+                | camera::camera::CameraController::Walking(_) => camera::camera::CameraController::Orbit(
+                    camera::camera::OrbitController::from_camera(&self.camera, 5.0),
+                ),
+                camera::camera::CameraController::Orbit(_) => camera::camera::CameraController::FirstPerson,
+            }),
+            (KeyCode::KeyV, true) => match self.controller {
+                camera::camera::CameraController::FirstPerson => self.set_controller(
+                    camera::camera::CameraController::Walking(character::character::CharacterController::default()),
+                ),
+                camera::camera::CameraController::Walking(_) => self.set_controller(camera::camera::CameraController::FirstPerson),
+                camera::camera::CameraController::Orbit(_) => {}
+            },
+            _ => {}
+        }
+    }
 
-            // Camera movement
-            (KeyCode::KeyW, true) => self.camera.move_forward(increment),
-            (KeyCode::KeyA, true) => self.camera.move_left(increment),
-            (KeyCode::KeyS, true) => self.camera.move_backward(increment),
-            (KeyCode::KeyD, true) => self.camera.move_right(increment),
-            (KeyCode::Space, true) => self.camera.move_up(increment),
-            (KeyCode::ShiftLeft, true) => self.camera.move_down(increment),
+    // Left clicks select the object under pick_position()
+    pub fn handle_mouse_button(&mut self, button: MouseButton, is_pressed: bool) {
+        self.input.set_button(button, is_pressed);
 
-            _ => {}
+        if button != MouseButton::Left {
+            return;
+        }
+
+        // The orbit controller drags with the left button, there only clicks that
+        // did not move pick on release
+        let orbiting = matches!(self.controller, camera::camera::CameraController::Orbit(_));
+
+        if is_pressed {
+            self.click_start = self.input.cursor();
+            if !orbiting {
+                self.handle_click();
+            }
+        } else if orbiting {
+            let is_click = match (self.click_start.take(), self.input.cursor()) {
+                (Some(start), Some(end)) => (end[0] - start[0]).hypot(end[1] - start[1]) <= CLICK_DISTANCE,
+                _ => false,
+            };
+
+            if is_click {
+                self.handle_click();
+            }
+        }
+    }
+
+    pub fn handle_cursor(&mut self, cursor: Option<[f32; 2]>) {
+        self.input.set_cursor(cursor);
+    }
+
+    pub fn controller(&self) -> &camera::camera::CameraController {
+        &self.controller
+    }
+
+    pub fn controller_mut(&mut self) -> &mut camera::camera::CameraController {
+        &mut self.controller
+    }
+
+    // The orbit controller needs a visible cursor to drag with, first person and walking grab it.
+    pub fn set_controller(&mut self, controller: camera::camera::CameraController) {
+        self.controller = controller;
+        self.set_cursor_grabbed(!matches!(controller, camera::camera::CameraController::Orbit(_)));
+    }
+
+    fn set_cursor_grabbed(&mut self, grabbed: bool) {
+        self.cursor_grabbed = grabbed;
+
+        let window = match &self.window {
+            Some(window) => window,
+            None => return,
+        };
+
+        let mode = if grabbed {
+            winit::window::CursorGrabMode::Locked
+        } else {
+            winit::window::CursorGrabMode::None
+        };
+
+        if let Err(e) = window.set_cursor_grab(mode) {
+            log::warn!("Unable to grab cursor {}", e);
+        }
+        window.set_cursor_visible(!grabbed);
+    }
+
+    // Scroll wheel movement in lines, positive away from the user.
+    pub fn handle_scroll(&mut self, lines: f32) {
+        if let camera::camera::CameraController::Orbit(orbit) = &mut self.controller {
+            orbit.zoom(lines);
         }
     }
 
     pub fn handle_mouse(&mut self, mouse_delta_h: f64, mouse_delta_v: f64) {
-        self.camera.adjust_angle_h(mouse_delta_h as f32);
-        self.camera.adjust_angle_v(mouse_delta_v as f32, HALF_PI);
+        if let camera::camera::CameraController::Orbit(orbit) = &mut self.controller {
+            let is_panning = self.input.is_button_held(MouseButton::Right)
+                || (self.input.is_button_held(MouseButton::Left) && self.input.is_key_held(KeyCode::ShiftLeft));
+
+            if is_panning {
+                orbit.pan(mouse_delta_h as f32, -mouse_delta_v as f32);
+            } else if self.input.is_button_held(MouseButton::Left) {
+                orbit.orbit(mouse_delta_h as f32, mouse_delta_v as f32);
+            }
+
+            return;
+        }
+
+        match self.camera.mode {
+            camera::camera::CameraMode::Fps => {
+                self.camera.adjust_angle_h(mouse_delta_h as f32);
+                self.camera.adjust_angle_v(mouse_delta_v as f32, HALF_PI);
+            }
+            camera::camera::CameraMode::Free => {
+                self.camera.yaw(mouse_delta_h as f32 * self.camera.mouse_sensitivity);
+                self.camera.pitch(mouse_delta_v as f32 * self.camera.mouse_sensitivity);
+            }
+        }
     }
 
     pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.window.request_redraw();
-
-        if !self.is_surface_configured {
-            return Ok(());
+        if let Some(window) = &self.window {
+            window.request_redraw();
         }
 
-        let output = self.surface.get_current_texture()?;
+        let surface = match &self.surface {
+            Some(surface) if self.is_surface_configured => surface,
+            _ => return Ok(()),
+        };
+
+        let output = surface.get_current_texture()?;
         let view = output.texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let submit_start = std::time::Instant::now();
+
+        self.overlay.update(&self.device, &self.queue, &self.fps_counter, self.config.width, self.config.height);
+        self.queue.submit(std::iter::once(self.encode_frame(&view)));
+        output.present();
+
+        self.fps_counter.record(fps::fps::FrameSample {
+            frame_time: self.frame_times.frame_time,
+            update_time: self.frame_times.update_time,
+            submit_time: submit_start.elapsed().as_secs_f32(),
+        });
+
+        Ok(())
+    }
+
+    // Renders a single frame offscreen and reads it back, this works with and without a window.
+    pub fn render_image(&mut self) -> anyhow::Result<image::RgbaImage> {
+        self.update_uniforms();
+
+        let width = self.config.width;
+        let height = self.config.height;
+        let format = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        // Rows of a texture copy have to be aligned to 256 bytes
+        let unpadded_bytes_per_row = width * 4;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+            * wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+        let output_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        // A pipeline built for the surface format cannot draw into the offscreen texture
+        let pipelines = (self.config.format != format).then(|| RenderPipelines::new(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.shader,
+            format,
+            self.depth_settings,
+        ));
+
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Render Encoder"),
+            label: Some("Offscreen Encoder"),
         });
 
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(BACKGROUND_COLOR),
-                        store: wgpu::StoreOp::Store,
-                    },
-                    depth_slice: None,
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-            
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-            render_pass.draw(0..self.vertices.len() as u32, 0..1);
+        self.draw(&mut encoder, &view, pipelines.as_ref().unwrap_or(&self.render_pipelines));
+
+        if self.overlay.format() == format {
+            self.overlay.update(&self.device, &self.queue, &self.fps_counter, width, height);
+            self.overlay.draw(&mut encoder, &view);
         }
 
+        encoder.copy_texture_to_buffer(
+            wgpu::TexelCopyTextureInfo {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::TexelCopyBufferInfo {
+                buffer: &output_buffer,
+                layout: wgpu::TexelCopyBufferLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+
         self.queue.submit(std::iter::once(encoder.finish()));
-        output.present();
+
+        let buffer_slice = output_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::wait_indefinitely())?;
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = buffer_slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        output_buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels)
+            .ok_or_else(|| anyhow::anyhow!("Frame buffer does not match the image size"))
+    }
+
+    pub fn save_png(&mut self, path: impl AsRef<std::path::Path>) -> anyhow::Result<()> {
+        let image = self.render_image()?;
+        image.save_with_format(path, image::ImageFormat::Png)?;
 
         Ok(())
     }
 
+    fn encode_frame(&self, view: &wgpu::TextureView) -> wgpu::CommandBuffer {
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Render Encoder"),
+        });
+
+        self.draw(&mut encoder, view, &self.render_pipelines);
+        self.overlay.draw(&mut encoder, view);
+
+        encoder.finish()
+    }
+
+    fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView, pipelines: &RenderPipelines) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Render Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(BACKGROUND_COLOR),
+                    store: wgpu::StoreOp::Store,
+                },
+                depth_slice: None,
+            })],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(1.0),
+                    store: wgpu::StoreOp::Store,
+                }),
+                stencil_ops: None,
+            }),
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+
+        render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.light_bind_group, &[]);
+        self.meshes.draw(
+            &mut render_pass,
+            &self.materials,
+            object::object::gmlib::matrix::Vec3::from(self.camera.position),
+            |material| pipelines.get(material),
+        );
+    }
+
     fn update(&mut self) {
-        self.frame_times.sample_size = self.frame_times.sample_size + 1;
-        let time_elapsed = self.frame_times.delta_time.elapsed().as_secs_f32();
-        if time_elapsed >= 0.5 {
-            println!("fps: {:?}", self.frame_times.sample_size as f32 / time_elapsed);
-            println!("camera.position: {:?}\ncamera.direction(): {:?}\ncamera.angle_h: {:?}\ncamera.angle_v: {:?}\n", self.camera.position, self.camera.direction(), self.camera.angle_h, self.camera.angle_v);
-            //println!("{:?}", self.camera_uniform.position);
-            self.frame_times.sample_size = 0;
-            self.frame_times.delta_time = std::time::Instant::now();
+        let update_start = std::time::Instant::now();
+        self.frame_times.frame_time = (update_start - self.frame_times.last_update).as_secs_f32();
+        self.frame_times.last_update = update_start;
+
+        let steps = self.timestep.advance(self.frame_times.frame_time);
+        for _ in 0..steps {
+            self.fixed_update(self.timestep.delta_time());
         }
 
-        self.frame_times.sample_size = self.frame_times.sample_size + 1;
-        
-        self.camera_uniform.update(self.camera);
-        
-        for i in 0..self.vertices.len() {
-            self.vertices[i].camera_matrix = self.camera.matrix();
-            self.vertices[i].camera_position = self.camera.position;
+        self.update_uniforms();
+
+        self.frame_times.update_time = update_start.elapsed().as_secs_f32();
+    }
+
+    fn fixed_update(&mut self, delta_time: f32) {
+        let position = self.camera.position;
+
+        self.update_world_matrices();
+        self.physics.step(&mut self.collisions, &mut self.meshes, delta_time);
+        self.collisions.update(&self.meshes);
+
+        self.update_camera(delta_time);
+
+        // Taken out while running, so the callback can borrow the state
+        if let Some(mut fixed_update) = self.fixed_update.take() {
+            fixed_update(self, delta_time);
+            self.fixed_update.get_or_insert(fixed_update);
         }
 
+        self.camera_motion = std::array::from_fn(|i| self.camera.position[i] - position[i]);
+
+        // Presses are seen by exactly one tick
+        self.input.end_frame();
+    }
+
+    fn update_camera(&mut self, delta_time: f32) {
+        if let camera::camera::CameraController::Orbit(orbit) = &mut self.controller {
+            orbit.update(&mut self.camera, delta_time);
+            return;
+        }
+
+        if let camera::camera::CameraController::Walking(character) = &mut self.controller {
+            let movement = [
+                self.input.axis(KeyCode::KeyA, KeyCode::KeyD),
+                self.input.axis(KeyCode::KeyS, KeyCode::KeyW),
+            ];
+            let jump = self.input.is_key_held(KeyCode::Space);
+            let sprint = self.input.is_key_held(KeyCode::ControlLeft);
+
+            self.camera.update_turning(delta_time);
+            character.update(&mut self.camera, &self.collisions, movement, jump, sprint, delta_time);
+            return;
+        }
+
+        let movement = [
+            self.input.axis(KeyCode::KeyA, KeyCode::KeyD),
+            self.input.axis(KeyCode::ShiftLeft, KeyCode::Space),
+            self.input.axis(KeyCode::KeyS, KeyCode::KeyW),
+        ];
+        let sprint = self.input.is_key_held(KeyCode::ControlLeft);
+
+        // Roll only does something in CameraMode::Free
+        let roll = self.input.axis(KeyCode::KeyQ, KeyCode::KeyE);
+        if roll != 0.0 {
+            self.camera.roll(roll * self.camera.roll_speed * delta_time);
+        }
+
+        self.camera.update_turning(delta_time);
+        self.camera.update_movement(movement, sprint, delta_time);
+    }
+
+    fn update_world_matrices(&mut self) {
+        let meshes = &mut self.meshes;
+        self.scene.update_world_matrices(|mesh, world| meshes.set_transform(mesh, world));
+    }
+
+    fn update_uniforms(&mut self) {
+        // The rendered frame lies alpha ticks past the last one, draw the camera
+        // between its last two positions so motion stays smooth
+        let alpha = self.timestep.alpha();
+        let mut camera = self.camera;
+        for i in 0..3 {
+            camera.position[i] -= self.camera_motion[i] * (1.0 - alpha);
+        }
+
+        self.camera_uniform.update(camera);
+
+        self.update_world_matrices();
+        self.meshes.upload(&self.device, &self.queue);
+        self.materials.upload(&self.device, &self.queue, &self.textures);
+
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
-        self.vertex_buffer = self.device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: bytemuck::cast_slice(&self.vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            }
-        );
+        if let Some(lights_uniform) = self.lights.take_changes() {
+            self.queue.write_buffer(
+                &self.light_buffer,
+                0,
+                bytemuck::cast_slice(&[lights_uniform]),
+            );
+        }
     }
 }
 
 impl App {
-    pub fn new() -> Self {
-        Self { state: None }
+    pub fn new(setup: impl FnOnce(&mut State) + 'static) -> Self {
+        Self {
+            state: None,
+            setup: Some(Box::new(setup)),
+        }
     }
 }
 
 impl ApplicationHandler<State> for App {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
-        let mut window_attributes = Window::default_attributes();
+        let window_attributes = Window::default_attributes();
         let window = Arc::new(event_loop.create_window(window_attributes).unwrap());
-        self.state = Some(pollster::block_on(State::new(window)).unwrap());
+        let mut state = pollster::block_on(State::new(window)).unwrap();
+
+        if let Some(setup) = self.setup.take() {
+            setup(&mut state);
+        }
+
+        self.state = Some(state);
     }
 
     #[allow(unused_mut)]
@@ -541,19 +1554,29 @@ impl ApplicationHandler<State> for App {
                 match state.render() {
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => {
-                        let size = state.window.inner_size();
-                        state.resize(size.width, size.height);
+                        if let Some(size) = state.window.as_ref().map(|window| window.inner_size()) {
+                            state.resize(size.width, size.height);
+                        }
                     }
                     Err(e) => {
                         log::error!("Unable to render {}", e);
                     }
                 }
             }
-            WindowEvent::MouseInput { state, button, .. } => match (button, state.is_pressed()) {
-                (MouseButton::Left, true) => {}
-                (MouseButton::Left, false) => {}
-                _ => {}
-            },
+            WindowEvent::MouseInput { state: button_state, button, .. } => {
+                state.handle_mouse_button(button, button_state.is_pressed());
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                state.handle_cursor(Some([position.x as f32, position.y as f32]));
+            }
+            WindowEvent::CursorLeft { .. } => state.handle_cursor(None),
+            // Release events are not delivered to unfocused windows
+            WindowEvent::Focused(false) => state.input.clear(),
+            WindowEvent::MouseWheel { delta, .. } => state.handle_scroll(match delta {
+                MouseScrollDelta::LineDelta(_, lines) => lines,
+                // Roughly one line per 20 pixels
+                MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+            }),
             WindowEvent::KeyboardInput {
                 event: KeyEvent {
                     physical_key: PhysicalKey::Code(code),
@@ -566,24 +1589,24 @@ impl ApplicationHandler<State> for App {
         }
     }
 
-    fn device_event(&mut self, event_loop: &ActiveEventLoop, device_id: DeviceId, event: winit::event::DeviceEvent) {
+    fn device_event(&mut self, _event_loop: &ActiveEventLoop, _device_id: DeviceId, event: winit::event::DeviceEvent) {
         let state = match &mut self.state {
             Some(canvas) => canvas,
             None => return,
         };
 
-        match event {
-            DeviceEvent::MouseMotion { delta } => state.handle_mouse(-delta.0, -delta.1),
-            _ => {}
+        if let DeviceEvent::MouseMotion { delta } = event {
+            state.handle_mouse(-delta.0, -delta.1);
         }
     }
 }
 
-pub fn run() -> anyhow::Result<()> {
+// The setup closure runs once the window and GPU are ready, use it to fill the scene.
+pub fn run(setup: impl FnOnce(&mut State) + 'static) -> anyhow::Result<()> {
     env_logger::init();
     
     let event_loop = EventLoop::with_user_event().build()?;
-    let mut app = App::new();
+    let mut app = App::new(setup);
     event_loop.run_app(&mut app)?;
 
     Ok(())
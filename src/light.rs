@@ -0,0 +1,232 @@
+// Tree structure of Lights:
+//
+// * lights: BTreeMap<LightId, Light>
+//   - kind: LightKind (Directional, Point, Spot)
+//   - position: [f32; 3]
+//   - direction: [f32; 3]
+//   - color: [f32; 3]
+//   - intensity: f32
+//   - attenuation: [f32; 3]
+// * ambient: [f32; 3]
+//
+// The shader renders unlit vertex colors while there are no lights.
+
+pub mod light {
+    use std::collections::BTreeMap;
+
+    // Has to match the array size in shader.wgsl
+    pub const MAX_LIGHTS: usize = 32;
+
+    const KIND_DIRECTIONAL: u32 = 0;
+    const KIND_POINT: u32 = 1;
+    const KIND_SPOT: u32 = 2;
+
+    #[derive(Copy, Clone, Debug, PartialEq)]
+    pub enum LightKind {
+        Directional,
+        Point,
+        // Cone angles in radians, the light fades out between the inner and outer angle
+        Spot { inner_angle: f32, outer_angle: f32 },
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct Light {
+        pub kind: LightKind,
+        pub position: [f32; 3],
+        pub direction: [f32; 3],
+        pub color: [f32; 3],
+        pub intensity: f32,
+        // Constant, linear and quadratic falloff over distance, ignored by directional lights
+        pub attenuation: [f32; 3],
+    }
+
+    impl Light {
+        pub fn directional(direction: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+            Self {
+                kind: LightKind::Directional,
+                position: [0.0, 0.0, 0.0],
+                direction,
+                color,
+                intensity,
+                attenuation: [1.0, 0.0, 0.0],
+            }
+        }
+
+        pub fn point(position: [f32; 3], color: [f32; 3], intensity: f32) -> Self {
+            Self {
+                kind: LightKind::Point,
+                position,
+                direction: [0.0, 0.0, 1.0],
+                color,
+                intensity,
+                attenuation: [1.0, 0.09, 0.032],
+            }
+        }
+
+        pub fn spot(
+            position: [f32; 3],
+            direction: [f32; 3],
+            inner_angle: f32,
+            outer_angle: f32,
+            color: [f32; 3],
+            intensity: f32,
+        ) -> Self {
+            Self {
+                kind: LightKind::Spot { inner_angle, outer_angle },
+                position,
+                direction,
+                color,
+                intensity,
+                attenuation: [1.0, 0.09, 0.032],
+            }
+        }
+
+        fn to_raw(self) -> LightRaw {
+            let (kind, inner_cone_cos, outer_cone_cos) = match self.kind {
+                LightKind::Directional => (KIND_DIRECTIONAL, 0.0, 0.0),
+                LightKind::Point => (KIND_POINT, 0.0, 0.0),
+                LightKind::Spot { inner_angle, outer_angle } => {
+                    // smoothstep is undefined unless the outer cone is strictly wider, an
+                    // outer angle at or inside the inner one gives a hard edge instead
+                    let inner_cone_cos = inner_angle.cos();
+                    (KIND_SPOT, inner_cone_cos, outer_angle.cos().min(inner_cone_cos - 1e-4))
+                }
+            };
+
+            LightRaw {
+                position: self.position,
+                kind,
+                direction: self.direction,
+                intensity: self.intensity,
+                color: self.color,
+                inner_cone_cos,
+                attenuation: self.attenuation,
+                outer_cone_cos,
+            }
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct LightRaw {
+        pub position: [f32; 3],
+        pub kind: u32,
+        pub direction: [f32; 3],
+        pub intensity: f32,
+        pub color: [f32; 3],
+        pub inner_cone_cos: f32,
+        pub attenuation: [f32; 3],
+        pub outer_cone_cos: f32,
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct LightsUniform {
+        pub ambient: [f32; 3],
+        pub count: u32,
+        pub lights: [LightRaw; MAX_LIGHTS],
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct LightId(u32);
+
+    pub struct Lights {
+        lights: BTreeMap<LightId, Light>,
+        next_id: u32,
+        ambient: [f32; 3],
+        // Set whenever the uniform has to be uploaded again
+        dirty: bool,
+    }
+
+    impl Default for Lights {
+        fn default() -> Self {
+            Self {
+                lights: BTreeMap::new(),
+                next_id: 0,
+                ambient: [0.1, 0.1, 0.1],
+                dirty: false,
+            }
+        }
+    }
+
+    impl Lights {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Returns None once MAX_LIGHTS lights exist.
+        pub fn add(&mut self, light: Light) -> Option<LightId> {
+            if self.lights.len() >= MAX_LIGHTS {
+                return None;
+            }
+
+            let id = LightId(self.next_id);
+            self.next_id += 1;
+
+            self.lights.insert(id, light);
+            self.dirty = true;
+
+            Some(id)
+        }
+
+        pub fn remove(&mut self, id: LightId) -> Option<Light> {
+            self.dirty = true;
+            self.lights.remove(&id)
+        }
+
+        pub fn get(&self, id: LightId) -> Option<&Light> {
+            self.lights.get(&id)
+        }
+
+        pub fn get_mut(&mut self, id: LightId) -> Option<&mut Light> {
+            self.dirty = true;
+            self.lights.get_mut(&id)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (LightId, &Light)> {
+            self.lights.iter().map(|(id, light)| (*id, light))
+        }
+
+        pub fn len(&self) -> usize {
+            self.lights.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.lights.is_empty()
+        }
+
+        pub fn ambient(&self) -> [f32; 3] {
+            self.ambient
+        }
+
+        pub fn set_ambient(&mut self, ambient: [f32; 3]) {
+            self.ambient = ambient;
+            self.dirty = true;
+        }
+
+        // Returns the uniform if anything changed since the last call.
+        pub fn take_changes(&mut self) -> Option<LightsUniform> {
+            if !self.dirty {
+                return None;
+            }
+
+            self.dirty = false;
+
+            Some(self.uniform())
+        }
+
+        pub fn uniform(&self) -> LightsUniform {
+            let mut uniform = LightsUniform {
+                ambient: self.ambient,
+                count: self.lights.len() as u32,
+                lights: [bytemuck::Zeroable::zeroed(); MAX_LIGHTS],
+            };
+
+            for (raw, light) in uniform.lights.iter_mut().zip(self.lights.values()) {
+                *raw = light.to_raw();
+            }
+
+            uniform
+        }
+    }
+}
@@ -1,11 +1,51 @@
-#[path="./lib.rs"]
-mod wgpu_3d_engine;
-
 use wgpu_3d_engine::run;
+use wgpu_3d_engine::light::light::Light;
+use wgpu_3d_engine::object::object::{Object, Triangle, Vertex};
+
+
+fn triangle(positions: [[f32; 3]; 3], color: [f32; 3]) -> Triangle {
+    let color = [color[0], color[1], color[2], 1.0];
+
+    Triangle::new([
+        Vertex::new(positions[0], color),
+        Vertex::new(positions[1], color),
+        Vertex::new(positions[2], color),
+    ])
+}
 
+fn demo_scene() -> Vec<Object> {
+    let tetrahedron = Object::new([0.0, 0.0, 0.0], vec!(
+        triangle([[-2.0, -0.35, 2.0], [-1.5, 0.0, 2.0], [-1.8, 0.6, 2.0]], [0.9, 0.0, 0.0]),
+        triangle([[-1.5, 0.0, 2.0], [-2.0, -0.35, 2.0], [-1.75, 2.4, 8.0]], [0.0, 0.8, 0.0]),
+        triangle([[-1.5, 0.0, 2.0], [-1.75, 2.4, 8.0], [-1.8, 0.6, 2.0]], [0.0, 0.5, 0.7]),
+        triangle([[-2.0, -0.35, 2.0], [-1.8, 0.6, 2.0], [-1.75, 2.4, 8.0]], [0.1, 0.1, 0.8]),
+    ), true);
+
+    let plank = Object::new([0.0, 0.0, 0.0], vec!(
+        triangle([[0.0, 0.0, 1.0], [200.0, 0.0, 1.0], [200.0, 1.0, 1.0]], [0.5, 1.5, 0.7]),
+        triangle([[0.0, 0.0, 1.0], [200.0, 1.0, 1.0], [0.0, 1.0, 1.0]], [0.1, 1.1, 0.7]),
+        triangle([[0.0, 0.0, 1.0], [200.0, 1.0, 1.0], [200.0, 0.0, 1.0]], [0.5, 1.5, 0.7]),
+        triangle([[0.0, 0.0, -1.0], [0.0, 1.0, -1.0], [200.0, 1.0, -1.0]], [0.1, 1.1, 0.7]),
+    ), true);
+
+    let slope = Object::new([0.0, 0.0, 0.0], vec!(
+        triangle([[0.0, 0.0, 2.0], [1.0, 1.0, 2.0], [0.0, 1.0, 3.0]], [0.3, 0.1, 0.1]),
+        triangle([[0.0, 0.0, 3.0], [1.0, 1.0, 3.0], [0.0, 1.0, 4.0]], [0.0, 0.7, 0.1]),
+    ), true);
+
+    vec!(tetrahedron, plank, slope)
+}
 
 fn main() {
-    run().unwrap();
+    run(|state| {
+        for mut object in demo_scene() {
+            object.compute_flat_normals();
+            state.add_object(object);
+        }
+
+        state.set_ambient_light([0.4, 0.4, 0.4]);
+        state.add_light(Light::directional([0.3, -1.0, 0.5], [1.0, 1.0, 1.0], 0.8));
+    }).unwrap();
 }
 
 
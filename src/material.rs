@@ -0,0 +1,354 @@
+// Tree structure of MaterialRegistry:
+//
+// * materials: BTreeMap<MaterialId, MaterialEntry>
+//   - material: Material
+//     * base_color: [f32; 4]
+//     * base_color_texture: Option<TextureId>
+//     * roughness: f32
+//     * specular: f32
+//     * emissive: [f32; 3]
+//     * alpha_mode: AlphaMode (Opaque, Mask, Blend)
+//     * double_sided: bool
+//   - bind_group: Option<wgpu::BindGroup>
+// * default: MaterialEntry
+// * layout: wgpu::BindGroupLayout
+// * buffer: wgpu::Buffer
+// * stride: u64
+// * capacity: u32
+// * next_id: u32
+// * texture_generation: u64
+// * dirty: bool
+//
+// The surface of an object is described by its material, the vertex colors
+// are multiplied with the base color and the base color texture. Objects
+// without a material, or with one that has been removed, use the default
+// material. Every material gets a slot in one uniform buffer and a bind group
+// holding its slot and its texture. Any change rewrites the whole buffer and
+// rebuilds the bind groups on the next upload, which is cheap for the handful
+// of materials a scene has.
+//
+// Masked materials discard fragments below the alpha cutoff, blended ones are
+// drawn after everything else without writing depth. Double-sided materials
+// are drawn without back-face culling and lit from the side facing the camera.
+
+pub mod material {
+    use std::collections::BTreeMap;
+
+    use crate::texture::texture::{TextureId, TextureRegistry};
+
+    const ALPHA_OPAQUE: u32 = 0;
+    const ALPHA_MASK: u32 = 1;
+    const ALPHA_BLEND: u32 = 2;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Default)]
+    pub enum AlphaMode {
+        // Alpha is ignored
+        #[default]
+        Opaque,
+        // Fragments with a lower alpha are cut out
+        Mask { cutoff: f32 },
+        Blend,
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct Material {
+        pub base_color: [f32; 4],
+        pub base_color_texture: Option<TextureId>,
+        // 0 is a mirror-like highlight, 1 a wide and dull one
+        pub roughness: f32,
+        // Strength of the highlight
+        pub specular: f32,
+        // Added to the lit color, so it shows in the dark
+        pub emissive: [f32; 3],
+        pub alpha_mode: AlphaMode,
+        pub double_sided: bool,
+    }
+
+    impl Default for Material {
+        fn default() -> Self {
+            Self {
+                base_color: [1.0, 1.0, 1.0, 1.0],
+                base_color_texture: None,
+                roughness: 0.5,
+                specular: 0.5,
+                emissive: [0.0, 0.0, 0.0],
+                alpha_mode: AlphaMode::Opaque,
+                double_sided: false,
+            }
+        }
+    }
+
+    impl Material {
+        pub fn color(base_color: [f32; 4]) -> Self {
+            Self {
+                base_color,
+                ..Self::default()
+            }
+        }
+
+        pub fn textured(texture: TextureId) -> Self {
+            Self {
+                base_color_texture: Some(texture),
+                ..Self::default()
+            }
+        }
+
+        pub fn is_blended(&self) -> bool {
+            self.alpha_mode == AlphaMode::Blend
+        }
+
+        fn to_uniform(self) -> MaterialUniform {
+            let (alpha_mode, alpha_cutoff) = match self.alpha_mode {
+                AlphaMode::Opaque => (ALPHA_OPAQUE, 0.0),
+                AlphaMode::Mask { cutoff } => (ALPHA_MASK, cutoff),
+                AlphaMode::Blend => (ALPHA_BLEND, 0.0),
+            };
+
+            MaterialUniform {
+                base_color: self.base_color,
+                emissive: self.emissive,
+                roughness: self.roughness.clamp(0.0, 1.0),
+                specular: self.specular,
+                alpha_mode,
+                alpha_cutoff,
+                _padding: 0,
+            }
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    struct MaterialUniform {
+        base_color: [f32; 4],
+        emissive: [f32; 3],
+        roughness: f32,
+        specular: f32,
+        alpha_mode: u32,
+        alpha_cutoff: f32,
+        _padding: u32,
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MaterialId(u32);
+
+    struct MaterialEntry {
+        material: Material,
+        // None until the first upload
+        bind_group: Option<wgpu::BindGroup>,
+    }
+
+    impl MaterialEntry {
+        fn new(material: Material) -> Self {
+            Self {
+                material,
+                bind_group: None,
+            }
+        }
+    }
+
+    pub struct MaterialRegistry {
+        materials: BTreeMap<MaterialId, MaterialEntry>,
+        default: MaterialEntry,
+        layout: wgpu::BindGroupLayout,
+        buffer: wgpu::Buffer,
+        // Distance between two slots, bind group offsets have to be aligned
+        stride: u64,
+        capacity: u32,
+        next_id: u32,
+        // Of the TextureRegistry the bind groups were built with
+        texture_generation: u64,
+        // Set whenever the buffer or the bind groups are out of date
+        dirty: bool,
+    }
+
+    impl MaterialRegistry {
+        pub fn new(device: &wgpu::Device) -> Self {
+            let layout = device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: wgpu::BufferSize::new(size_of::<MaterialUniform>() as u64),
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                multisampled: false,
+                                view_dimension: wgpu::TextureViewDimension::D2,
+                                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                            count: None,
+                        },
+                    ],
+                    label: Some("Material bind group layout"),
+                }
+            );
+
+            let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+            let stride = (size_of::<MaterialUniform>() as u64).div_ceil(alignment) * alignment;
+            let capacity = 16;
+
+            Self {
+                materials: BTreeMap::new(),
+                default: MaterialEntry::new(Material::default()),
+                layout,
+                buffer: Self::create_buffer(device, stride, capacity),
+                stride,
+                capacity,
+                next_id: 0,
+                texture_generation: 0,
+                dirty: true,
+            }
+        }
+
+        fn create_buffer(device: &wgpu::Device, stride: u64, capacity: u32) -> wgpu::Buffer {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Material buffer"),
+                size: stride * capacity as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        }
+
+        // Layout of the material bind group the shader expects at group 3
+        pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+            &self.layout
+        }
+
+        pub fn add(&mut self, material: Material) -> MaterialId {
+            let id = MaterialId(self.next_id);
+            self.next_id += 1;
+
+            self.materials.insert(id, MaterialEntry::new(material));
+            self.dirty = true;
+
+            id
+        }
+
+        // False if the material does not exist
+        pub fn update(&mut self, id: MaterialId, material: Material) -> bool {
+            match self.materials.get_mut(&id) {
+                Some(entry) => {
+                    entry.material = material;
+                    self.dirty = true;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        // Objects still using the material fall back to the default one
+        pub fn remove(&mut self, id: MaterialId) -> Option<Material> {
+            let entry = self.materials.remove(&id)?;
+            self.dirty = true;
+
+            Some(entry.material)
+        }
+
+        pub fn get(&self, id: MaterialId) -> Option<&Material> {
+            self.materials.get(&id).map(|entry| &entry.material)
+        }
+
+        // The default material for None and for materials that do not exist
+        pub fn get_or_default(&self, id: Option<MaterialId>) -> &Material {
+            &self.entry(id).material
+        }
+
+        pub fn default_material(&self) -> &Material {
+            &self.default.material
+        }
+
+        pub fn set_default_material(&mut self, material: Material) {
+            self.default.material = material;
+            self.dirty = true;
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (MaterialId, &Material)> {
+            self.materials.iter().map(|(id, entry)| (*id, &entry.material))
+        }
+
+        pub fn len(&self) -> usize {
+            self.materials.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.materials.is_empty()
+        }
+
+        // Has to be uploaded after every change, see upload().
+        pub fn bind_group(&self, id: Option<MaterialId>) -> &wgpu::BindGroup {
+            self.entry(id).bind_group.as_ref()
+                .or(self.default.bind_group.as_ref())
+                .expect("Materials have to be uploaded before drawing")
+        }
+
+        fn entry(&self, id: Option<MaterialId>) -> &MaterialEntry {
+            id.and_then(|id| self.materials.get(&id)).unwrap_or(&self.default)
+        }
+
+        // Writes the material uniforms and rebuilds the bind groups, has to be called
+        // before drawing whenever materials or textures changed.
+        pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, textures: &TextureRegistry) {
+            if !self.dirty && self.texture_generation == textures.generation() {
+                return;
+            }
+
+            // The default material takes the first slot
+            let count = self.materials.len() as u32 + 1;
+            if count > self.capacity {
+                self.capacity = count.next_power_of_two();
+                self.buffer = Self::create_buffer(device, self.stride, self.capacity);
+            }
+
+            let stride = self.stride as usize;
+            let mut contents = vec![0u8; stride * count as usize];
+
+            let entries = std::iter::once(&mut self.default).chain(self.materials.values_mut());
+            for (slot, entry) in entries.enumerate() {
+                contents[slot * stride..slot * stride + size_of::<MaterialUniform>()]
+                    .copy_from_slice(bytemuck::bytes_of(&entry.material.to_uniform()));
+
+                let texture = textures.get_or_white(entry.material.base_color_texture);
+                entry.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                                buffer: &self.buffer,
+                                offset: slot as u64 * self.stride,
+                                size: wgpu::BufferSize::new(size_of::<MaterialUniform>() as u64),
+                            }),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: wgpu::BindingResource::TextureView(&texture.view),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                        },
+                    ],
+                    label: Some("Material bind group"),
+                }));
+            }
+
+            queue.write_buffer(&self.buffer, 0, &contents);
+            self.texture_generation = textures.generation();
+            self.dirty = false;
+        }
+    }
+}
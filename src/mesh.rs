@@ -0,0 +1,769 @@
+// Tree structure of MeshRegistry:
+//
+// * meshes: BTreeMap<MeshId, Mesh>
+//   - object: Object
+//   - transform: Mat4x4
+//   - bounds: Option<Aabb>
+//   - highlight: [f32; 4]
+//   - instances: BTreeMap<InstanceId, Instance>
+//     * transform: Mat4x4
+//     * tint: [f32; 4]
+//   - next_instance: u32
+//   - instance_buffer: Option<wgpu::Buffer>
+//   - vertex_buffer: wgpu::Buffer
+//   - index_buffer: wgpu::Buffer
+//   - index_format: wgpu::IndexFormat
+//   - vertex_count: u32
+//   - index_count: u32
+//   - slot: u32
+// * models: ModelBuffer
+// * next_id: u32
+// * weld_tolerance: f32
+// * identity_instance: wgpu::Buffer
+//
+// Vertices stay in object space, every mesh gets a slot in one uniform buffer
+// holding its model matrix, selected with a dynamic offset when drawing. The
+// slot also holds the highlight color mixed over the shaded mesh and the pick
+// id written to the ID buffer, which is the mesh id plus one so 0 stays free
+// for the background.
+//
+// Corners shared by several triangles are welded into a single vertex when all
+// of their attributes match. With a weld tolerance, positions only have to be
+// within that distance of each other, a vertex is welded to the first earlier
+// one in reach. Positions are sorted into a grid of cells as wide as the
+// tolerance, so only the surrounding cells have to be searched. Meshes with up
+// to 65535 vertices get 16 bit indices.
+//
+// A mesh with instances is drawn once per instance in a single draw call,
+// each copy placed by its instance transform inside the mesh transform and
+// its vertex colors multiplied by the tint. Without instances the mesh is
+// drawn once, with a shared identity instance. Colliders are built for each
+// instance the same way, while a rigid body moves the mesh with all of its
+// instances.
+//
+// Meshes are drawn with the material of their object. Blended materials are
+// drawn last, sorted back to front by the center of their bounds. The
+// instances of a mesh share its draw call, so they are not sorted.
+
+pub mod mesh {
+    use std::collections::{BTreeMap, HashMap};
+    use wgpu::util::DeviceExt;
+
+    use crate::{Instance as InstanceRaw, Vertex};
+    use crate::collision::collision::Aabb;
+    use crate::object::object::Object;
+    use crate::object::object::gmlib::matrix::*;
+    use crate::material::material::{Material, MaterialId, MaterialRegistry};
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct MeshId(u32);
+
+    // Unique within its mesh
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct InstanceId(u32);
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Instance {
+        // Relative to the mesh transform
+        pub transform: Mat4x4,
+        // Multiplies the vertex colors
+        pub tint: [f32; 4],
+    }
+
+    impl Default for Instance {
+        fn default() -> Self {
+            Self {
+                transform: UNIT_MAT4X4,
+                tint: [1.0, 1.0, 1.0, 1.0],
+            }
+        }
+    }
+
+    impl Instance {
+        pub fn new(transform: Mat4x4, tint: [f32; 4]) -> Self {
+            Self {
+                transform,
+                tint,
+            }
+        }
+    }
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    pub struct ModelUniform {
+        pub model: [[f32; 4]; 4],
+        // Columns of a mat3x3, padded to 16 bytes each like WGSL expects
+        pub normal: [[f32; 4]; 3],
+        // Mixed over the shaded color by its alpha
+        pub highlight: [f32; 4],
+        pub pick_id: u32,
+        _padding: [u32; 3],
+    }
+
+    impl ModelUniform {
+        pub fn new(model: Mat4x4, highlight: [f32; 4], pick_id: u32) -> Self {
+            let normal = model.normal_matrix().transpose().to_array();
+
+            Self {
+                // WGSL matrices are column-major
+                model: model.transpose().to_array(),
+                normal: normal.map(|[x, y, z]| [x, y, z, 0.0]),
+                highlight,
+                pick_id,
+                _padding: [0; 3],
+            }
+        }
+    }
+
+    pub struct Mesh {
+        pub object: Object,
+        // World transform, usually set from the scene graph
+        transform: Mat4x4,
+        // Object space, None without triangles
+        bounds: Option<Aabb>,
+        highlight: [f32; 4],
+        instances: BTreeMap<InstanceId, Instance>,
+        next_instance: u32,
+        // None until the first upload with instances
+        instance_buffer: Option<wgpu::Buffer>,
+        // Set whenever the instance buffer is out of date
+        instances_dirty: bool,
+        vertex_buffer: wgpu::Buffer,
+        index_buffer: wgpu::Buffer,
+        index_format: wgpu::IndexFormat,
+        vertex_count: u32,
+        index_count: u32,
+        // Position in the model buffer
+        slot: u32,
+    }
+
+    impl Mesh {
+        // weld_tolerance is the distance up to which positions are welded, 0 only welds identical vertices
+        pub fn new(device: &wgpu::Device, object: Object, weld_tolerance: f32) -> Self {
+            let (vertices, indices) = weld(&Self::vertices(&object), weld_tolerance);
+
+            // Indices are only ever compared against the vertex count, 0xFFFF included
+            let (index_format, index_bytes) = if vertices.len() <= u16::MAX as usize {
+                let indices: Vec<u16> = indices.iter().map(|&index| index as u16).collect();
+                (wgpu::IndexFormat::Uint16, bytemuck::cast_slice(&indices).to_vec())
+            } else {
+                (wgpu::IndexFormat::Uint32, bytemuck::cast_slice(&indices).to_vec())
+            };
+
+            let vertex_buffer = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Mesh vertex buffer"),
+                    contents: bytemuck::cast_slice(&vertices),
+                    usage: wgpu::BufferUsages::VERTEX,
+                }
+            );
+
+            let index_buffer = device.create_buffer_init(
+                &wgpu::util::BufferInitDescriptor {
+                    label: Some("Mesh index buffer"),
+                    contents: &index_bytes,
+                    usage: wgpu::BufferUsages::INDEX,
+                }
+            );
+
+            let bounds = Aabb::from_points(
+                object.triangles.iter().flat_map(|triangle| triangle.vertices.iter().map(|vertex| vertex.position)),
+            );
+
+            Self {
+                object,
+                transform: UNIT_MAT4X4,
+                bounds,
+                highlight: [0.0; 4],
+                instances: BTreeMap::new(),
+                next_instance: 0,
+                instance_buffer: None,
+                instances_dirty: false,
+                vertex_buffer,
+                index_buffer,
+                index_format,
+                vertex_count: vertices.len() as u32,
+                index_count: indices.len() as u32,
+                slot: 0,
+            }
+        }
+
+        pub fn transform(&self) -> Mat4x4 {
+            self.transform
+        }
+
+        pub fn bounds(&self) -> Option<&Aabb> {
+            self.bounds.as_ref()
+        }
+
+        // Unique vertices after welding
+        pub fn vertex_count(&self) -> u32 {
+            self.vertex_count
+        }
+
+        pub fn index_count(&self) -> u32 {
+            self.index_count
+        }
+
+        pub fn index_format(&self) -> wgpu::IndexFormat {
+            self.index_format
+        }
+
+        pub fn highlight(&self) -> [f32; 4] {
+            self.highlight
+        }
+
+        pub fn instance(&self, id: InstanceId) -> Option<&Instance> {
+            self.instances.get(&id)
+        }
+
+        pub fn instances(&self) -> impl Iterator<Item = (InstanceId, &Instance)> {
+            self.instances.iter().map(|(id, instance)| (*id, instance))
+        }
+
+        // Model matrices of every copy that is drawn, the instance is None for a
+        // mesh drawn without instances.
+        pub fn world_matrices(&self) -> Vec<(Option<InstanceId>, Mat4x4)> {
+            let model = self.model_matrix();
+
+            if self.instances.is_empty() {
+                return vec![(None, model)];
+            }
+
+            self.instances.iter()
+                .map(|(id, instance)| (Some(*id), model * instance.transform))
+                .collect()
+        }
+
+        // The world transform followed by the position of the object
+        pub fn model_matrix(&self) -> Mat4x4 {
+            let position = self.object.position;
+
+            self.transform * Mat4x4::from([
+                1.0, 0.0, 0.0, position.x_1,
+                0.0, 1.0, 0.0, position.x_2,
+                0.0, 0.0, 1.0, position.x_3,
+                0.0, 0.0, 0.0, 1.0,
+            ])
+        }
+
+        // Flattens the triangles of an object into a plain triangle list
+        fn vertices(object: &Object) -> Vec<Vertex> {
+            let mut vertices = Vec::with_capacity(object.triangles.len() * 3);
+
+            for triangle in object.triangles.iter() {
+                for vertex in triangle.vertices.iter() {
+                    // Vertices without their own normal use the one of the triangle
+                    let normal = if vertex.normal.magnitude() > 0.0 {
+                        vertex.normal
+                    } else {
+                        triangle.normal
+                    };
+
+                    vertices.push(Vertex::from_object(
+                        vertex.position,
+                        vertex.color,
+                        normal,
+                        vertex.uv,
+                    ));
+                }
+            }
+
+            vertices
+        }
+
+        // identity_instance is used when the mesh has no instances
+        pub fn draw(&self, render_pass: &mut wgpu::RenderPass, identity_instance: &wgpu::Buffer) {
+            if self.index_count == 0 {
+                return;
+            }
+
+            let (instance_buffer, instance_count) = match &self.instance_buffer {
+                Some(buffer) if !self.instances.is_empty() => (buffer, self.instances.len() as u32),
+                _ => (identity_instance, 1),
+            };
+
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            render_pass.set_index_buffer(self.index_buffer.slice(..), self.index_format);
+            render_pass.draw_indexed(0..self.index_count, 0, 0..instance_count);
+        }
+
+        fn upload_instances(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+            if !self.instances_dirty {
+                return;
+            }
+            self.instances_dirty = false;
+
+            if self.instances.is_empty() {
+                return;
+            }
+
+            let instances: Vec<InstanceRaw> = self.instances.values().map(InstanceRaw::from_mesh).collect();
+            let contents: &[u8] = bytemuck::cast_slice(&instances);
+
+            match &self.instance_buffer {
+                Some(buffer) if buffer.size() >= contents.len() as u64 => queue.write_buffer(buffer, 0, contents),
+                _ => {
+                    // Room to grow, so adding one instance at a time does not reallocate every time
+                    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some("Mesh instance buffer"),
+                        size: (instances.len().next_power_of_two() * size_of::<InstanceRaw>()) as u64,
+                        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    });
+                    queue.write_buffer(&buffer, 0, contents);
+                    self.instance_buffer = Some(buffer);
+                }
+            }
+        }
+    }
+
+    // Unique vertices and the indices rebuilding the triangle list from them
+    fn weld(vertices: &[Vertex], tolerance: f32) -> (Vec<Vertex>, Vec<u32>) {
+        // Adding 0.0 turns -0.0 into 0.0, so both weld
+        let bits = |value: f32| (value + 0.0).to_bits();
+        // Far out or with a tiny tolerance the cell saturates, the distance check still holds
+        let cell = |position: [f32; 3]| if tolerance > 0.0 {
+            position.map(|value| (value / tolerance).floor() as i64)
+        } else {
+            position.map(|value| bits(value) as i64)
+        };
+        // Without a tolerance the cell is the exact position, there are no neighbours to search
+        let reach = if tolerance > 0.0 { 1 } else { 0 };
+        let within = |a: [f32; 3], b: [f32; 3]| {
+            tolerance == 0.0 || (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum::<f32>() <= tolerance * tolerance
+        };
+
+        let mut unique: Vec<Vertex> = Vec::new();
+        let mut indices = Vec::with_capacity(vertices.len());
+        let mut lookup: HashMap<_, Vec<u32>> = HashMap::new();
+
+        for vertex in vertices {
+            let attributes = (
+                vertex.color.map(bits),
+                vertex.normal.map(bits),
+                vertex.uv.map(bits),
+            );
+            let [x, y, z] = cell(vertex.position);
+
+            let mut welded: Option<u32> = None;
+            for dx in -reach..=reach {
+                for dy in -reach..=reach {
+                    for dz in -reach..=reach {
+                        let neighbour = [x.wrapping_add(dx), y.wrapping_add(dy), z.wrapping_add(dz)];
+                        let Some(candidates) = lookup.get(&(neighbour, attributes)) else {
+                            continue;
+                        };
+
+                        let nearby = candidates.iter()
+                            .copied()
+                            .find(|&index| within(unique[index as usize].position, vertex.position));
+                        if let Some(index) = nearby {
+                            welded = Some(welded.map_or(index, |welded| welded.min(index)));
+                        }
+                    }
+                }
+            }
+
+            let index = welded.unwrap_or_else(|| {
+                unique.push(*vertex);
+                let index = unique.len() as u32 - 1;
+                lookup.entry(([x, y, z], attributes)).or_default().push(index);
+                index
+            });
+            indices.push(index);
+        }
+
+        (unique, indices)
+    }
+
+    struct ModelBuffer {
+        layout: wgpu::BindGroupLayout,
+        buffer: wgpu::Buffer,
+        bind_group: wgpu::BindGroup,
+        // Distance between two slots, dynamic offsets have to be aligned
+        stride: u64,
+        capacity: u32,
+        // Set whenever the buffer contents are out of date
+        dirty: bool,
+    }
+
+    impl ModelBuffer {
+        fn new(device: &wgpu::Device) -> Self {
+            let layout = device.create_bind_group_layout(
+                &wgpu::BindGroupLayoutDescriptor {
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: true,
+                                min_binding_size: wgpu::BufferSize::new(size_of::<ModelUniform>() as u64),
+                            },
+                            count: None,
+                        }
+                    ],
+                    label: Some("Model bind group layout"),
+                }
+            );
+
+            let alignment = device.limits().min_uniform_buffer_offset_alignment as u64;
+            let stride = (size_of::<ModelUniform>() as u64).div_ceil(alignment) * alignment;
+            let capacity = 64;
+            let (buffer, bind_group) = Self::create_buffer(device, &layout, stride, capacity);
+
+            Self {
+                layout,
+                buffer,
+                bind_group,
+                stride,
+                capacity,
+                dirty: true,
+            }
+        }
+
+        fn create_buffer(
+            device: &wgpu::Device,
+            layout: &wgpu::BindGroupLayout,
+            stride: u64,
+            capacity: u32,
+        ) -> (wgpu::Buffer, wgpu::BindGroup) {
+            let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Model buffer"),
+                size: stride * capacity as u64,
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            });
+
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(size_of::<ModelUniform>() as u64),
+                        }),
+                    }
+                ],
+                label: Some("Model bind group"),
+            });
+
+            (buffer, bind_group)
+        }
+    }
+
+    pub struct MeshRegistry {
+        meshes: BTreeMap<MeshId, Mesh>,
+        models: ModelBuffer,
+        next_id: u32,
+        weld_tolerance: f32,
+        identity_instance: wgpu::Buffer,
+    }
+
+    impl MeshRegistry {
+        pub fn new(device: &wgpu::Device) -> Self {
+            Self {
+                meshes: BTreeMap::new(),
+                models: ModelBuffer::new(device),
+                next_id: 0,
+                weld_tolerance: 0.0,
+                identity_instance: device.create_buffer_init(
+                    &wgpu::util::BufferInitDescriptor {
+                        label: Some("Identity instance buffer"),
+                        contents: bytemuck::bytes_of(&InstanceRaw::IDENTITY),
+                        usage: wgpu::BufferUsages::VERTEX,
+                    }
+                ),
+            }
+        }
+
+        // Layout of the model bind group the shader expects at group 2
+        pub fn bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+            &self.models.layout
+        }
+
+        pub fn add(&mut self, device: &wgpu::Device, object: Object) -> MeshId {
+            let id = MeshId(self.next_id);
+            self.next_id += 1;
+
+            self.meshes.insert(id, Mesh::new(device, object, self.weld_tolerance));
+            self.models.dirty = true;
+
+            id
+        }
+
+        // Returns the object back to the caller, the GPU buffers are freed on drop.
+        pub fn remove(&mut self, id: MeshId) -> Option<Object> {
+            self.models.dirty = true;
+            self.meshes.remove(&id).map(|mesh| mesh.object)
+        }
+
+        // Keeps the transform of the mesh
+        pub fn replace(&mut self, device: &wgpu::Device, id: MeshId, object: Object) -> Option<Object> {
+            let mesh = self.meshes.get_mut(&id)?;
+            let mut replacement = Mesh::new(device, object, self.weld_tolerance);
+            replacement.transform = mesh.transform;
+            replacement.highlight = mesh.highlight;
+            replacement.instances = std::mem::take(&mut mesh.instances);
+            replacement.next_instance = mesh.next_instance;
+            replacement.instances_dirty = true;
+
+            self.models.dirty = true;
+
+            Some(std::mem::replace(mesh, replacement).object)
+        }
+
+        pub fn weld_tolerance(&self) -> f32 {
+            self.weld_tolerance
+        }
+
+        // Applies to meshes added or replaced afterwards
+        pub fn set_weld_tolerance(&mut self, weld_tolerance: f32) {
+            self.weld_tolerance = weld_tolerance.max(0.0);
+        }
+
+        pub fn set_transform(&mut self, id: MeshId, transform: Mat4x4) {
+            if let Some(mesh) = self.meshes.get_mut(&id) {
+                mesh.transform = transform;
+                self.models.dirty = true;
+            }
+        }
+
+        // None uses the default material, false if the mesh does not exist
+        pub fn set_material(&mut self, id: MeshId, material: Option<MaterialId>) -> bool {
+            match self.meshes.get_mut(&id) {
+                Some(mesh) => {
+                    mesh.object.material = material;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        // None if the mesh does not exist
+        pub fn add_instance(&mut self, mesh: MeshId, instance: Instance) -> Option<InstanceId> {
+            let mesh = self.meshes.get_mut(&mesh)?;
+            let id = InstanceId(mesh.next_instance);
+            mesh.next_instance += 1;
+
+            mesh.instances.insert(id, instance);
+            mesh.instances_dirty = true;
+
+            Some(id)
+        }
+
+        // False if the mesh or the instance does not exist
+        pub fn update_instance(&mut self, mesh: MeshId, id: InstanceId, instance: Instance) -> bool {
+            let Some(mesh) = self.meshes.get_mut(&mesh) else {
+                return false;
+            };
+
+            match mesh.instances.get_mut(&id) {
+                Some(current) => {
+                    *current = instance;
+                    mesh.instances_dirty = true;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        // The mesh is drawn on its own again once its last instance is removed
+        pub fn remove_instance(&mut self, mesh: MeshId, id: InstanceId) -> Option<Instance> {
+            let mesh = self.meshes.get_mut(&mesh)?;
+            let instance = mesh.instances.remove(&id)?;
+            mesh.instances_dirty = true;
+
+            Some(instance)
+        }
+
+        pub fn clear_instances(&mut self, mesh: MeshId) {
+            if let Some(mesh) = self.meshes.get_mut(&mesh) {
+                mesh.instances.clear();
+                mesh.instances_dirty = true;
+            }
+        }
+
+        // An alpha of 0 turns the highlight off
+        pub fn set_highlight(&mut self, id: MeshId, highlight: [f32; 4]) {
+            if let Some(mesh) = self.meshes.get_mut(&id) {
+                mesh.highlight = highlight;
+                self.models.dirty = true;
+            }
+        }
+
+        // The mesh a value read from the ID buffer belongs to
+        pub fn from_pick_id(&self, pick_id: u32) -> Option<MeshId> {
+            let id = MeshId(pick_id.checked_sub(1)?);
+            self.meshes.contains_key(&id).then_some(id)
+        }
+
+        // Writes the model matrices and instances of all meshes, has to be called before
+        // drawing whenever meshes, transforms or instances changed.
+        pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+            for mesh in self.meshes.values_mut() {
+                mesh.upload_instances(device, queue);
+            }
+
+            if !self.models.dirty {
+                return;
+            }
+
+            let count = self.meshes.len().max(1) as u32;
+            if count > self.models.capacity {
+                let capacity = count.next_power_of_two();
+                let (buffer, bind_group) = ModelBuffer::create_buffer(device, &self.models.layout, self.models.stride, capacity);
+
+                self.models.buffer = buffer;
+                self.models.bind_group = bind_group;
+                self.models.capacity = capacity;
+            }
+
+            let stride = self.models.stride as usize;
+            let mut contents = vec![0u8; stride * self.meshes.len()];
+
+            for (slot, (id, mesh)) in self.meshes.iter_mut().enumerate() {
+                mesh.slot = slot as u32;
+
+                let uniform = ModelUniform::new(mesh.model_matrix(), mesh.highlight, id.0 + 1);
+                contents[slot * stride..slot * stride + size_of::<ModelUniform>()]
+                    .copy_from_slice(bytemuck::bytes_of(&uniform));
+            }
+
+            if !contents.is_empty() {
+                queue.write_buffer(&self.models.buffer, 0, &contents);
+            }
+            self.models.dirty = false;
+        }
+
+        pub fn get(&self, id: MeshId) -> Option<&Mesh> {
+            self.meshes.get(&id)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (MeshId, &Mesh)> {
+            self.meshes.iter().map(|(id, mesh)| (*id, mesh))
+        }
+
+        pub fn len(&self) -> usize {
+            self.meshes.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.meshes.is_empty()
+        }
+
+        // pipeline selects the pipeline for the material of each mesh, eye is the
+        // camera position blended meshes are sorted by.
+        pub fn draw<'p>(
+            &self,
+            render_pass: &mut wgpu::RenderPass,
+            materials: &MaterialRegistry,
+            eye: Vec3,
+            pipeline: impl Fn(&Material) -> &'p wgpu::RenderPipeline,
+        ) {
+            let (mut blended, opaque): (Vec<&Mesh>, Vec<&Mesh>) = self.meshes.values()
+                .partition(|mesh| materials.get_or_default(mesh.object.material).is_blended());
+
+            let distance = |mesh: &Mesh| {
+                let center = mesh.bounds.map_or(Vec3::from([0.0, 0.0, 0.0]), |bounds| (bounds.min + bounds.max) * 0.5);
+                let center = mesh.model_matrix() * Vec4::from([center.x_1, center.x_2, center.x_3, 1.0]);
+                (Vec3::from([center.x_1, center.x_2, center.x_3]) - eye).magnitude()
+            };
+            blended.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+
+            let mut current: Option<&wgpu::RenderPipeline> = None;
+
+            for mesh in opaque.into_iter().chain(blended) {
+                let material = materials.get_or_default(mesh.object.material);
+                let mesh_pipeline = pipeline(material);
+                if current.is_none_or(|current| !std::ptr::eq(current, mesh_pipeline)) {
+                    render_pass.set_pipeline(mesh_pipeline);
+                    current = Some(mesh_pipeline);
+                }
+
+                let offset = mesh.slot as u64 * self.models.stride;
+                render_pass.set_bind_group(2, &self.models.bind_group, &[offset as u32]);
+                render_pass.set_bind_group(3, materials.bind_group(mesh.object.material), &[]);
+                mesh.draw(render_pass, &self.identity_instance);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn vertex(position: [f32; 3]) -> Vertex {
+            Vertex {
+                position,
+                color: [1.0, 1.0, 1.0, 1.0],
+                normal: [0.0, 0.0, 1.0],
+                uv: [0.0, 0.0],
+            }
+        }
+
+        #[test]
+        fn without_tolerance_only_identical_vertices_weld() {
+            let vertices = [vertex([1.0, 2.0, 3.0]), vertex([1.0, 2.0, 3.0001]), vertex([1.0, 2.0, 3.0])];
+            let (unique, indices) = weld(&vertices, 0.0);
+
+            assert_eq!(unique.len(), 2);
+            assert_eq!(indices, [0, 1, 0]);
+        }
+
+        #[test]
+        fn signed_zeros_weld() {
+            let (unique, _) = weld(&[vertex([0.0, 0.0, 0.0]), vertex([-0.0, 0.0, -0.0])], 0.0);
+
+            assert_eq!(unique.len(), 1);
+        }
+
+        #[test]
+        fn tolerance_welds_across_cell_boundaries() {
+            // 0.099 and 0.101 fall into different cells of a 0.1 grid
+            let vertices = [vertex([0.099, 0.0, 0.0]), vertex([0.101, 0.0, 0.0]), vertex([0.25, 0.0, 0.0])];
+            let (unique, indices) = weld(&vertices, 0.1);
+
+            assert_eq!(unique.len(), 2);
+            assert_eq!(indices, [0, 0, 1]);
+        }
+
+        #[test]
+        fn tolerance_is_a_distance() {
+            // Neighbouring cells on every axis, but further apart than the tolerance
+            let (unique, _) = weld(&[vertex([0.0, 0.0, 0.0]), vertex([0.09, 0.09, 0.09])], 0.1);
+
+            assert_eq!(unique.len(), 2);
+        }
+
+        #[test]
+        fn different_attributes_do_not_weld() {
+            let mut colored = vertex([0.0, 0.0, 0.0]);
+            colored.color = [1.0, 0.0, 0.0, 1.0];
+            let mut textured = vertex([0.0, 0.0, 0.0]);
+            textured.uv = [0.5, 0.5];
+
+            let (unique, indices) = weld(&[vertex([0.0, 0.0, 0.0]), colored, textured], 0.1);
+
+            assert_eq!(unique.len(), 3);
+            assert_eq!(indices, [0, 1, 2]);
+        }
+
+        #[test]
+        fn huge_coordinates_do_not_overflow() {
+            let vertices = [
+                vertex([f32::MAX, -f32::MAX, 1e30]),
+                vertex([f32::MAX, -f32::MAX, 1e30]),
+                vertex([-f32::MAX, f32::MAX, -1e30]),
+            ];
+            let (unique, indices) = weld(&vertices, 1e-6);
+
+            assert_eq!(unique.len(), 2);
+            assert_eq!(indices, [0, 0, 1]);
+        }
+    }
+}
@@ -0,0 +1,490 @@
+// Wavefront OBJ/MTL loader.
+//
+// Every group (`g`) or object (`o`) statement in the OBJ file starts a new
+// Object, returned together with the name of its statement. Triangles before
+// the first statement form a group without a name. OBJ files are right-handed
+// while the engine is left-handed, so the z axis of positions and normals is
+// flipped on import.
+
+pub mod obj_loader {
+    use std::collections::HashMap;
+    use std::path::Path;
+    use anyhow::Context;
+
+    use crate::object::object::gmlib::matrix::*;
+    use crate::object::object::{Object, Triangle, Vertex};
+
+    #[derive(Debug)]
+    pub struct ParseError {
+        pub line: usize,
+        pub message: String,
+    }
+
+    impl std::fmt::Display for ParseError {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "line {}: {}", self.line, self.message)
+        }
+    }
+
+    impl std::error::Error for ParseError {}
+
+    #[derive(Debug, Clone)]
+    pub struct ObjMaterial {
+        pub diffuse: [f32; 3],
+        pub alpha: f32,
+        pub diffuse_texture: Option<String>,
+    }
+
+    impl Default for ObjMaterial {
+        fn default() -> Self {
+            Self {
+                diffuse: [1.0, 1.0, 1.0],
+                alpha: 1.0,
+                diffuse_texture: None,
+            }
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct ObjGroup {
+        // The names following g or o, None before the first statement or without names
+        pub name: Option<String>,
+        pub object: Object,
+    }
+
+    impl ObjGroup {
+        fn new(name: Option<String>, triangles: Vec<Triangle>) -> Self {
+            Self {
+                name,
+                object: Object::new([0.0, 0.0, 0.0], triangles, false),
+            }
+        }
+    }
+
+    impl ObjMaterial {
+        fn color(&self) -> Vec4 {
+            Vec4::from([self.diffuse[0], self.diffuse[1], self.diffuse[2], self.alpha])
+        }
+    }
+
+    // Loads an OBJ file together with the MTL libraries it references,
+    // library paths are resolved relative to the OBJ file.
+    pub fn load_obj(path: impl AsRef<Path>) -> anyhow::Result<Vec<ObjGroup>> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read {}", path.display()))?;
+
+        let directory = path.parent().unwrap_or(Path::new(""));
+        let mut materials = HashMap::new();
+
+        for library in material_libraries(&source) {
+            let library_path = directory.join(library);
+            let library_source = std::fs::read_to_string(&library_path)
+                .with_context(|| format!("Unable to read {}", library_path.display()))?;
+
+            materials.extend(
+                parse_mtl(&library_source)
+                    .with_context(|| format!("Unable to parse {}", library_path.display()))?
+            );
+        }
+
+        parse_obj(&source, &materials)
+            .with_context(|| format!("Unable to parse {}", path.display()))
+    }
+
+    fn material_libraries(source: &str) -> Vec<&str> {
+        source.lines()
+            .filter_map(|line| line.trim().strip_prefix("mtllib"))
+            .filter(|rest| rest.starts_with(char::is_whitespace))
+            .flat_map(|rest| rest.split_whitespace())
+            .collect()
+    }
+
+    pub fn parse_mtl(source: &str) -> Result<HashMap<String, ObjMaterial>, ParseError> {
+        let mut materials = HashMap::new();
+        let mut current: Option<(String, ObjMaterial)> = None;
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split_whitespace();
+
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            if keyword == "newmtl" {
+                let name = words.next().ok_or_else(|| ParseError {
+                    line: line_number,
+                    message: "newmtl without a material name".to_string(),
+                })?;
+
+                if let Some((name, material)) = current.take() {
+                    materials.insert(name, material);
+                }
+
+                current = Some((name.to_string(), ObjMaterial::default()));
+                continue;
+            }
+
+            let material = match current.as_mut() {
+                Some((_, material)) => material,
+                None => return Err(ParseError {
+                    line: line_number,
+                    message: format!("'{}' before any newmtl statement", keyword),
+                }),
+            };
+
+            match keyword {
+                "Kd" => material.diffuse = parse_floats::<3>(words, line_number)?,
+                "d" => material.alpha = parse_floats::<1>(words, line_number)?[0],
+                "Tr" => material.alpha = 1.0 - parse_floats::<1>(words, line_number)?[0],
+                "map_Kd" => material.diffuse_texture = words.last().map(str::to_string),
+                // Unsupported statements are skipped
+                _ => {}
+            }
+        }
+
+        if let Some((name, material)) = current.take() {
+            materials.insert(name, material);
+        }
+
+        Ok(materials)
+    }
+
+    pub fn parse_obj(source: &str, materials: &HashMap<String, ObjMaterial>) -> Result<Vec<ObjGroup>, ParseError> {
+        let mut positions: Vec<Vec3> = Vec::new();
+        let mut uvs: Vec<Vec2> = Vec::new();
+        let mut normals: Vec<Vec3> = Vec::new();
+
+        let default_material = ObjMaterial::default();
+        let mut material = &default_material;
+
+        let mut groups = Vec::new();
+        let mut name: Option<String> = None;
+        let mut triangles: Vec<Triangle> = Vec::new();
+
+        for (index, line) in source.lines().enumerate() {
+            let line_number = index + 1;
+            let mut words = line.split_whitespace();
+
+            let keyword = match words.next() {
+                Some(keyword) if !keyword.starts_with('#') => keyword,
+                _ => continue,
+            };
+
+            match keyword {
+                "v" => {
+                    let [x, y, z] = parse_floats::<3>(words.by_ref().take(3), line_number)?;
+                    positions.push(Vec3::from([x, y, -z]));
+                }
+                "vt" => {
+                    // The v coordinate is optional, w is ignored
+                    let u = parse_float(words.next(), line_number)?;
+                    let v = match words.next() {
+                        Some(word) => parse_float(Some(word), line_number)?,
+                        None => 0.0,
+                    };
+                    // OBJ has the v axis pointing up, wgpu textures have it pointing down
+                    uvs.push(Vec2::from([u, 1.0 - v]));
+                }
+                "vn" => {
+                    let [x, y, z] = parse_floats::<3>(words, line_number)?;
+                    normals.push(Vec3::from([x, y, -z]));
+                }
+                "f" => {
+                    let corners = words
+                        .map(|word| parse_corner(word, &positions, &uvs, &normals, line_number))
+                        .collect::<Result<Vec<_>, _>>()?;
+
+                    if corners.len() < 3 {
+                        return Err(ParseError {
+                            line: line_number,
+                            message: format!("face with {} vertices, at least 3 are required", corners.len()),
+                        });
+                    }
+
+                    // Fan triangulation, n-gons are expected to be convex
+                    for i in 1..(corners.len() - 1) {
+                        triangles.push(triangle([corners[0], corners[i], corners[i + 1]], material.color()));
+                    }
+                }
+                "g" | "o" => {
+                    if !triangles.is_empty() {
+                        groups.push(ObjGroup::new(name.take(), std::mem::take(&mut triangles)));
+                    }
+
+                    let names: Vec<&str> = words.collect();
+                    name = (!names.is_empty()).then(|| names.join(" "));
+                }
+                "usemtl" => {
+                    let name = words.next().ok_or_else(|| ParseError {
+                        line: line_number,
+                        message: "usemtl without a material name".to_string(),
+                    })?;
+
+                    material = match materials.get(name) {
+                        Some(material) => material,
+                        None => {
+                            log::warn!("line {}: unknown material '{}'", line_number, name);
+                            &default_material
+                        }
+                    };
+                }
+                // Unsupported statements (s, l, p, mtllib, ...) are skipped
+                _ => {}
+            }
+        }
+
+        if !triangles.is_empty() {
+            groups.push(ObjGroup::new(name, triangles));
+        }
+
+        Ok(groups)
+    }
+
+    #[derive(Clone, Copy)]
+    struct Corner {
+        position: Vec3,
+        uv: Option<Vec2>,
+        normal: Option<Vec3>,
+    }
+
+    fn triangle(corners: [Corner; 3], color: Vec4) -> Triangle {
+        let vertex = |corner: Corner| Vertex {
+            position: corner.position,
+            color,
+            uv: corner.uv.unwrap_or(Vec2::from([0.0, 0.0])),
+            normal: corner.normal
+                .filter(|normal| normal.magnitude() > f32::EPSILON)
+                .map(Vec3::normalize)
+                .unwrap_or(Vec3::from([0.0, 0.0, 0.0])),
+        };
+
+        let mut triangle = Triangle::new([vertex(corners[0]), vertex(corners[1]), vertex(corners[2])]);
+
+        // Prefer the normals of the file, fall back to the winding of the face
+        let normal = match (corners[0].normal, corners[1].normal, corners[2].normal) {
+            (Some(n_1), Some(n_2), Some(n_3)) => n_1 + n_2 + n_3,
+            _ => (corners[1].position - corners[0].position) % (corners[2].position - corners[0].position),
+        };
+
+        if normal.magnitude() > f32::EPSILON {
+            triangle.normal = normal.normalize();
+        }
+
+        triangle
+    }
+
+    // Parses a face corner of the form v, v/vt, v//vn or v/vt/vn.
+    fn parse_corner(
+        word: &str,
+        positions: &[Vec3],
+        uvs: &[Vec2],
+        normals: &[Vec3],
+        line_number: usize,
+    ) -> Result<Corner, ParseError> {
+        let mut parts = word.split('/');
+
+        let position = match parts.next() {
+            Some(part) if !part.is_empty() => *lookup(part, positions, "vertex", line_number)?,
+            _ => return Err(ParseError {
+                line: line_number,
+                message: format!("face vertex '{}' has no position index", word),
+            }),
+        };
+
+        let uv = match parts.next() {
+            Some(part) if !part.is_empty() => Some(*lookup(part, uvs, "texture coordinate", line_number)?),
+            _ => None,
+        };
+
+        let normal = match parts.next() {
+            Some(part) if !part.is_empty() => Some(*lookup(part, normals, "normal", line_number)?),
+            _ => None,
+        };
+
+        Ok(Corner { position, uv, normal })
+    }
+
+    // OBJ indices start at 1, negative indices count back from the last element.
+    fn lookup<'a, T>(part: &str, values: &'a [T], kind: &str, line_number: usize) -> Result<&'a T, ParseError> {
+        let index: i64 = part.parse().map_err(|_| ParseError {
+            line: line_number,
+            message: format!("invalid {} index '{}'", kind, part),
+        })?;
+
+        let resolved = match index {
+            0 => None,
+            index if index > 0 => Some(index - 1),
+            index => Some(values.len() as i64 + index),
+        };
+
+        resolved
+            .filter(|&i| i >= 0)
+            .and_then(|i| values.get(i as usize))
+            .ok_or_else(|| ParseError {
+                line: line_number,
+                message: format!("{} index {} out of range, {} defined", kind, index, values.len()),
+            })
+    }
+
+    fn parse_floats<'a, const N: usize>(
+        words: impl Iterator<Item = &'a str>,
+        line_number: usize,
+    ) -> Result<[f32; N], ParseError> {
+        let mut values = [0.0; N];
+        let mut count = 0;
+
+        for word in words {
+            if count == N {
+                return Err(ParseError {
+                    line: line_number,
+                    message: format!("expected {} numbers, found more", N),
+                });
+            }
+
+            values[count] = parse_float(Some(word), line_number)?;
+            count += 1;
+        }
+
+        if count < N {
+            return Err(ParseError {
+                line: line_number,
+                message: format!("expected {} numbers, found {}", N, count),
+            });
+        }
+
+        Ok(values)
+    }
+
+    fn parse_float(word: Option<&str>, line_number: usize) -> Result<f32, ParseError> {
+        let word = word.ok_or_else(|| ParseError {
+            line: line_number,
+            message: "expected a number, found end of line".to_string(),
+        })?;
+
+        word.parse().map_err(|_| ParseError {
+            line: line_number,
+            message: format!("invalid number '{}'", word),
+        })
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 1\n";
+
+        fn parse(source: &str) -> Result<Vec<ObjGroup>, ParseError> {
+            parse_obj(source, &HashMap::new())
+        }
+
+        fn positions(group: &ObjGroup) -> Vec<[f32; 3]> {
+            group.object.triangles.iter()
+                .flat_map(|triangle| triangle.vertices.iter().map(|vertex| vertex.position.to_array()))
+                .collect()
+        }
+
+        #[test]
+        fn groups_keep_their_names() {
+            let source = format!("{}f 1 2 3\no first\nf 1 2 3\ng left arm\nf 1 2 3\ng\nf 1 2 3\n", TRIANGLE);
+            let groups = parse(&source).unwrap();
+
+            let names: Vec<Option<&str>> = groups.iter().map(|group| group.name.as_deref()).collect();
+            assert_eq!(names, [None, Some("first"), Some("left arm"), None]);
+        }
+
+        #[test]
+        fn empty_groups_are_dropped() {
+            let source = format!("{}o empty\ng named\nf 1 2 3\n", TRIANGLE);
+            let groups = parse(&source).unwrap();
+
+            assert_eq!(groups.len(), 1);
+            assert_eq!(groups[0].name.as_deref(), Some("named"));
+        }
+
+        #[test]
+        fn negative_indices_count_back() {
+            let positive = parse(&format!("{}f 1 2 3\n", TRIANGLE)).unwrap();
+            let negative = parse(&format!("{}f -3 -2 -1\n", TRIANGLE)).unwrap();
+
+            assert_eq!(positions(&positive[0]), positions(&negative[0]));
+            // The z axis is flipped
+            assert_eq!(positions(&negative[0])[2], [0.0, 1.0, -1.0]);
+        }
+
+        #[test]
+        fn polygons_are_triangulated() {
+            let source = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n";
+            let groups = parse(source).unwrap();
+
+            assert_eq!(groups[0].object.triangles.len(), 2);
+        }
+
+        #[test]
+        fn malformed_faces_are_errors() {
+            let cases = [
+                ("f 1 2", "face with 2 vertices"),
+                ("f 1 2 4", "vertex index 4 out of range"),
+                ("f 1 2 -4", "vertex index -4 out of range"),
+                ("f 0 1 2", "vertex index 0 out of range"),
+                ("f 1 2 x", "invalid vertex index 'x'"),
+                ("f 1 2 /1", "has no position index"),
+                ("f 1/1 2 3", "texture coordinate index 1 out of range"),
+                ("f 1//1 2 3", "normal index 1 out of range"),
+            ];
+
+            for (face, message) in cases {
+                let error = parse(&format!("{}{}\n", TRIANGLE, face)).unwrap_err();
+
+                assert_eq!(error.line, 4, "{}", face);
+                assert!(error.message.contains(message), "{}: {}", face, error.message);
+            }
+        }
+
+        #[test]
+        fn unknown_materials_fall_back_to_white() {
+            let mut materials = HashMap::new();
+            materials.insert("red".to_string(), ObjMaterial { diffuse: [1.0, 0.0, 0.0], ..ObjMaterial::default() });
+
+            let source = format!("{}usemtl red\nf 1 2 3\nusemtl missing\nf 1 2 3\n", TRIANGLE);
+            let groups = parse_obj(&source, &materials).unwrap();
+
+            let colors: Vec<[f32; 4]> = groups[0].object.triangles.iter()
+                .map(|triangle| triangle.vertices[0].color.to_array())
+                .collect();
+            assert_eq!(colors, [[1.0, 0.0, 0.0, 1.0], [1.0, 1.0, 1.0, 1.0]]);
+        }
+
+        #[test]
+        fn materials_are_parsed() {
+            let source = "# comment\nnewmtl glass\nKd 0.5 0.5 1\nTr 0.75\nmap_Kd -bm 1 glass.png\nnewmtl solid\nd 1\n";
+            let materials = parse_mtl(source).unwrap();
+
+            let glass = &materials["glass"];
+            assert_eq!(glass.diffuse, [0.5, 0.5, 1.0]);
+            assert_eq!(glass.alpha, 0.25);
+            assert_eq!(glass.diffuse_texture.as_deref(), Some("glass.png"));
+            assert_eq!(materials["solid"].diffuse, [1.0, 1.0, 1.0]);
+        }
+
+        #[test]
+        fn malformed_materials_are_errors() {
+            let cases = [
+                ("Kd 1 1 1", 1, "before any newmtl"),
+                ("newmtl", 1, "without a material name"),
+                ("newmtl a\nKd 1 0", 2, "expected 3 numbers, found 2"),
+                ("newmtl a\nd 1 0", 2, "found more"),
+                ("newmtl a\nd half", 2, "invalid number 'half'"),
+            ];
+
+            for (source, line, message) in cases {
+                let error = parse_mtl(source).unwrap_err();
+
+                assert_eq!(error.line, line, "{}", source);
+                assert!(error.message.contains(message), "{}: {}", source, error.message);
+            }
+        }
+    }
+}
@@ -4,31 +4,86 @@
 // * triangles: Vec<Triangle>
 //   - vertices: Vertex
 //     * position: [f32; 3]
-//     * color: [f32; 3]
+//     * color: [f32; 4]
+//     * uv: [f32; 2]
+//     * normal: [f32; 3]
 //   - normal: [f32; 3]
 // * collision: bool
+// * material: Option<MaterialId>
 
 pub mod object {
-    mod gmlib;
+    pub mod gmlib;
+    use std::collections::HashMap;
     use gmlib::matrix::*;
 
+    use crate::material::material::MaterialId;
+
+    #[derive(Debug, Clone, Copy)]
     pub struct Vertex {
-        position: Vec3,
-        color: Vec4,
+        pub position: Vec3,
+        pub color: Vec4,
+        pub uv: Vec2,
+        // Zero when the vertex uses the normal of its triangle
+        pub normal: Vec3,
+    }
+
+    impl Vertex {
+        pub fn new(position: [f32; 3], color: [f32; 4]) -> Self {
+            Self {
+                position: Vec3::from(position),
+                color: Vec4::from(color),
+                uv: Vec2::from([0.0, 0.0]),
+                normal: Vec3::from([0.0, 0.0, 0.0]),
+            }
+        }
     }
 
+    #[derive(Debug, Clone, Copy)]
     pub struct Triangle {
-        vertices: [Vertex; 3],
-        normal: Vec3,
+        pub vertices: [Vertex; 3],
+        pub normal: Vec3,
+    }
+
+    impl Triangle {
+        pub fn new(vertices: [Vertex; 3]) -> Self {
+            Self {
+                vertices,
+                normal: Vec3::from([0.0, 0.0, 0.0]),
+            }
+        }
+
+        // Points out of the counter-clockwise front face, the length is twice the area.
+        pub fn face_normal(&self) -> Vec3 {
+            let [p_1, p_2, p_3] = self.vertices.map(|vertex| vertex.position);
+
+            (p_2 - p_1) % (p_3 - p_1)
+        }
+
+        // Sets the normal from the winding, degenerate triangles get a zero normal.
+        pub fn compute_normal(&mut self) {
+            self.normal = normalize_or_zero(self.face_normal());
+        }
     }
 
+    #[derive(Debug, Clone)]
     pub struct Object {
-        position: Vec3,
-        triangles: Vec<Triangle>,
-        collision: bool,
+        pub position: Vec3,
+        pub triangles: Vec<Triangle>,
+        pub collision: bool,
+        // None uses the default material
+        pub material: Option<MaterialId>,
     }
 
     impl Object {
+        pub fn new(position: [f32; 3], triangles: Vec<Triangle>, collision: bool) -> Self {
+            Self {
+                position: Vec3::from(position),
+                triangles,
+                collision,
+                material: None,
+            }
+        }
+
         pub fn rotate(&mut self, axis: Vec3, angle: f32, offset: Vec3) {
             for i in 0..self.triangles.len() {
                 self.triangles[i].vertices[0].position =
@@ -37,8 +92,69 @@ pub mod object {
                     Quaternion::rotate_offset(self.triangles[i].vertices[1].position, axis, angle, offset);
                 self.triangles[i].vertices[2].position =
                     Quaternion::rotate_offset(self.triangles[i].vertices[2].position, axis, angle, offset);
+
+                self.triangles[i].normal = Quaternion::rotate(self.triangles[i].normal, axis, angle);
+                for vertex in self.triangles[i].vertices.iter_mut() {
+                    vertex.normal = Quaternion::rotate(vertex.normal, axis, angle);
+                }
+            }
+        }
+
+        // Flat shading, every vertex takes the normal of its triangle.
+        pub fn compute_flat_normals(&mut self) {
+            for triangle in self.triangles.iter_mut() {
+                triangle.compute_normal();
+
+                for vertex in triangle.vertices.iter_mut() {
+                    vertex.normal = triangle.normal;
+                }
+            }
+        }
+
+        // Smooth shading, vertices at the same position average the normals of
+        // their triangles, weighted by area. Triangles meeting at an angle above
+        // crease_angle (radians) keep a hard edge between them.
+        pub fn compute_smooth_normals(&mut self, crease_angle: f32) {
+            for triangle in self.triangles.iter_mut() {
+                triangle.compute_normal();
+            }
+
+            let face_normals: Vec<Vec3> = self.triangles.iter().map(Triangle::face_normal).collect();
+
+            // Corners (triangle, vertex) sharing a position
+            let mut corners: HashMap<[u32; 3], Vec<(usize, usize)>> = HashMap::new();
+            for (t, triangle) in self.triangles.iter().enumerate() {
+                for (v, vertex) in triangle.vertices.iter().enumerate() {
+                    corners.entry(position_key(vertex.position)).or_default().push((t, v));
+                }
+            }
+
+            let crease_cos = crease_angle.cos();
+
+            for shared in corners.values() {
+                for &(t, v) in shared {
+                    let normal = self.triangles[t].normal;
+
+                    let sum = shared.iter()
+                        .filter(|&&(other, _)| self.triangles[other].normal * normal >= crease_cos)
+                        .fold(Vec3::from([0.0, 0.0, 0.0]), |sum, &(other, _)| sum + face_normals[other]);
+
+                    self.triangles[t].vertices[v].normal = normalize_or_zero(sum);
+                }
             }
         }
     }
-}
 
+    fn normalize_or_zero(vector: Vec3) -> Vec3 {
+        if vector.magnitude() > f32::EPSILON {
+            vector.normalize()
+        } else {
+            Vec3::from([0.0, 0.0, 0.0])
+        }
+    }
+
+    // Adding 0.0 turns -0.0 into 0.0, so both end up with the same bits
+    fn position_key(position: Vec3) -> [u32; 3] {
+        position.to_array().map(|value| (value + 0.0).to_bits())
+    }
+}
@@ -59,6 +59,7 @@
 //   - pow(mut self, exponent: u32) -> Mat3x3
 //   - determinant(&self) -> f32
 //   - inverse(&self) -> Self
+//   - transpose(self) -> Mat3x3
 //   - to_array(self) -> [[f32; 3]; 3]
 //   - Addition: Mat3x3 + Mat3x3 -> Mat3x3
 //   - Subtraction: Mat3x3 - Mat3x3 -> Mat3x3
@@ -75,6 +76,8 @@
 //   - pow(mut self, exponent: u32) -> Mat4x4
 //   - determinant(&self) -> f32
 //   - inverse(&self) -> Mat4x4
+//   - transpose(self) -> Mat4x4
+//   - normal_matrix(self) -> Mat3x3
 //   - to_array(self) -> [[f32; 4]; 4]
 //   - Addition: Mat4x4 + Mat4x4 -> Mat4x4
 //   - Subtraction: Mat4x4 - Mat4x4 -> Mat4x4
@@ -86,6 +89,28 @@
 //   - Implements std::fmt::Display
 //   - Unit matrix constant: UNIT_MAT4X4
 //
+// * Quaternion
+//   - from_axis_angle(axis: Vec3, angle: f32) -> Quaternion
+//   - from_axes(right: Vec3, up: Vec3, forward: Vec3) -> Quaternion
+//   - look_rotation(forward: Vec3, up: Vec3) -> Quaternion
+//   - conjugate(self) -> Quaternion
+//   - dot(self, rhs: Quaternion) -> f32
+//   - normalize(self) -> Quaternion
+//   - rotate_vector(self, vector: Vec3) -> Vec3
+//   - to_mat3x3(self) -> Mat3x3
+//   - slerp(self, target: Quaternion, t: f32) -> Quaternion
+//   - rotate(point: Vec3, axis: Vec3, angle: f32) -> Vec3
+//   - rotate_offset(point: Vec3, axis: Vec3, angle: f32, offset: Vec3) -> Vec3
+//   - Multiplication: Quaternion * Quaternion -> Quaternion, (a * b) rotates by b first, then by a
+//   - Unit quaternion constant: UNIT_QUATERNION
+//
+// * Ray
+//   - new(origin: Vec3, direction: Vec3) -> Ray
+//   - at(self, distance: f32) -> Vec3
+//   - intersect_triangle(self, triangle: [Vec3; 3]) -> Option<f32>
+//   - intersect_aabb(self, min: Vec3, max: Vec3) -> Option<f32>
+//   - intersect_sphere(self, center: Vec3, radius: f32) -> Option<f32>
+//
 
 pub mod matrix {
     #[derive(Debug, Clone, Copy)]
@@ -462,7 +487,7 @@ pub mod matrix {
             if exponent == 0 {
                 UNIT_MAT2X2
             } else {
-                for i in 0..(exponent - 1) { self = self * self; }
+                for _ in 0..(exponent - 1) { self = self * self; }
                 
                 self
             }
@@ -564,7 +589,7 @@ pub mod matrix {
     }
 
     // Mat2x2-Vec2 multiplication
-    impl std::ops::Mul<Vec2> for Mat3x3 {
+    impl std::ops::Mul<Vec2> for Mat2x2 {
         type Output = Vec2;
 
         fn mul(self, rhs: Vec2) -> Vec2 {
@@ -626,7 +651,7 @@ pub mod matrix {
             if exponent == 0 {
                 UNIT_MAT3X3
             } else {
-                for i in 0..(exponent - 1) { self = self * self; }
+                for _ in 0..(exponent - 1) { self = self * self; }
                 
                 self
             }
@@ -661,6 +686,14 @@ pub mod matrix {
             }
         }
 
+        pub fn transpose(self) -> Self {
+            Self {
+                x_11: self.x_11, x_12: self.x_21, x_13: self.x_31,
+                x_21: self.x_12, x_22: self.x_22, x_23: self.x_32,
+                x_31: self.x_13, x_32: self.x_23, x_33: self.x_33,
+            }
+        }
+
         pub fn to_array(self) -> [[f32; 3]; 3] {
             [
                 [self.x_11, self.x_12, self.x_13],
@@ -833,7 +866,7 @@ pub mod matrix {
             Self {
                 x_11: values[0],  x_12: values[1],  x_13: values[2],  x_14: values[3],
                 x_21: values[4],  x_22: values[5],  x_23: values[6],  x_24: values[7],
-                x_31: values[6],  x_32: values[7],  x_33: values[8],  x_34: values[11],
+                x_31: values[8],  x_32: values[9],  x_33: values[10], x_34: values[11],
                 x_41: values[12], x_42: values[13], x_43: values[14], x_44: values[15],
             }
         }
@@ -842,7 +875,7 @@ pub mod matrix {
             if exponent == 0 {
                 UNIT_MAT4X4
             } else {
-                for i in 0..(exponent - 1) { self = self * self; }
+                for _ in 0..(exponent - 1) { self = self * self; }
                 
                 self
             }
@@ -873,7 +906,7 @@ pub mod matrix {
 
         // Slow as tar shit. https://semath.info/src/inverse-cofactor-ex4.html
         pub fn inverse(&self) -> Self {
-            let determinant_self = 1.0 / self.determinant();
+            let determinant_self = self.determinant();
 
             Self {
                 x_11: Mat3x3 {
@@ -959,6 +992,25 @@ pub mod matrix {
             }
         }
 
+        pub fn transpose(self) -> Self {
+            Self {
+                x_11: self.x_11, x_12: self.x_21, x_13: self.x_31, x_14: self.x_41,
+                x_21: self.x_12, x_22: self.x_22, x_23: self.x_32, x_24: self.x_42,
+                x_31: self.x_13, x_32: self.x_23, x_33: self.x_33, x_34: self.x_43,
+                x_41: self.x_14, x_42: self.x_24, x_43: self.x_34, x_44: self.x_44,
+            }
+        }
+
+        // Inverse transpose of the upper left 3x3 part, transforms normals
+        // without the skew a non-uniform scale would give them.
+        pub fn normal_matrix(self) -> Mat3x3 {
+            Mat3x3::from([
+                self.x_11, self.x_12, self.x_13,
+                self.x_21, self.x_22, self.x_23,
+                self.x_31, self.x_32, self.x_33,
+            ]).inverse().transpose()
+        }
+
         pub fn to_array(self) -> [[f32; 4]; 4] {
             [
                 [self.x_11, self.x_12, self.x_13, self.x_14],
@@ -1192,34 +1244,110 @@ pub mod matrix {
         }
     } 
 
+    pub const UNIT_QUATERNION: Quaternion = Quaternion {
+        r: 1.0,
+        i: 0.0,
+        j: 0.0,
+        k: 0.0,
+    };
+
     impl Quaternion {
-        pub fn conjugate(self) -> Self {
-            Self {
-                r: self.r,
-                i: -self.i,
-                j: -self.j,
-                k: -self.k,
+        pub fn from_axis_angle(axis: Vec3, angle: f32) -> Self {
+            let axis = axis.normalize() * (0.5 * angle).sin();
+
+            Quaternion {
+                r: (0.5 * angle).cos(),
+                i: axis.x_1,
+                j: axis.x_2,
+                k: axis.x_3,
             }
         }
 
-        pub fn rotate(point: Vec3, axis: Vec3, angle: f32) -> Vec3 {
-            let rotation = axis.normalize() * (0.5 * angle).sin();
-
-            let rotation = Quaternion {
-                r: (0.5 * angle).cos(),
-                i: rotation.x_1,
-                j: rotation.x_3,
-                k: rotation.x_2,
+        // The rotation taking the x, y and z axes to right, up and forward,
+        // which have to be orthonormal.
+        pub fn from_axes(right: Vec3, up: Vec3, forward: Vec3) -> Self {
+            let trace = right.x_1 + up.x_2 + forward.x_3;
+
+            let quaternion = if trace > 0.0 {
+                let s = 0.5 / (trace + 1.0).sqrt();
+                Quaternion {
+                    r: 0.25 / s,
+                    i: (forward.x_2 - up.x_3) * s,
+                    j: (right.x_3 - forward.x_1) * s,
+                    k: (up.x_1 - right.x_2) * s,
+                }
+            } else if right.x_1 > up.x_2 && right.x_1 > forward.x_3 {
+                let s = 2.0 * (1.0 + right.x_1 - up.x_2 - forward.x_3).sqrt();
+                Quaternion {
+                    r: (forward.x_2 - up.x_3) / s,
+                    i: 0.25 * s,
+                    j: (up.x_1 + right.x_2) / s,
+                    k: (forward.x_1 + right.x_3) / s,
+                }
+            } else if up.x_2 > forward.x_3 {
+                let s = 2.0 * (1.0 + up.x_2 - right.x_1 - forward.x_3).sqrt();
+                Quaternion {
+                    r: (right.x_3 - forward.x_1) / s,
+                    i: (up.x_1 + right.x_2) / s,
+                    j: 0.25 * s,
+                    k: (forward.x_2 + up.x_3) / s,
+                }
+            } else {
+                let s = 2.0 * (1.0 + forward.x_3 - right.x_1 - up.x_2).sqrt();
+                Quaternion {
+                    r: (up.x_1 - right.x_2) / s,
+                    i: (forward.x_1 + right.x_3) / s,
+                    j: (forward.x_2 + up.x_3) / s,
+                    k: 0.25 * s,
+                }
             };
 
-            let point = Quaternion {
+            quaternion.normalize()
+        }
+
+        // Rotation that points the z axis along forward, keeping the y axis as close to up as possible.
+        pub fn look_rotation(forward: Vec3, up: Vec3) -> Self {
+            let forward = forward.normalize();
+            let mut right = forward % up;
+
+            // Looking straight along up, any right axis will do
+            if right.magnitude() < 1e-6 {
+                right = forward % Vec3::from([1.0, 0.0, 0.0]);
+                if right.magnitude() < 1e-6 {
+                    right = forward % Vec3::from([0.0, 0.0, 1.0]);
+                }
+            }
+
+            let right = right.normalize();
+            let up = right % forward;
+
+            Self::from_axes(right, up, forward)
+        }
+
+        pub fn dot(self, rhs: Quaternion) -> f32 {
+            self.r * rhs.r + self.i * rhs.i + self.j * rhs.j + self.k * rhs.k
+        }
+
+        pub fn normalize(self) -> Self {
+            let length = self.dot(self).sqrt();
+
+            Self {
+                r: self.r / length,
+                i: self.i / length,
+                j: self.j / length,
+                k: self.k / length,
+            }
+        }
+
+        pub fn rotate_vector(self, vector: Vec3) -> Vec3 {
+            let vector = Quaternion {
                 r: 0.0,
-                i: point.x_1,
-                j: point.x_2,
-                k: point.x_3,
+                i: vector.x_1,
+                j: vector.x_2,
+                k: vector.x_3,
             };
 
-            let result = rotation * point * rotation.conjugate();
+            let result = self * vector * self.conjugate();
 
             Vec3 {
                 x_1: result.i,
@@ -1228,6 +1356,60 @@ pub mod matrix {
             }
         }
 
+        // Rotation matrix of a unit quaternion, its columns are the rotated x, y and z axes
+        pub fn to_mat3x3(self) -> Mat3x3 {
+            let x = self.rotate_vector(Vec3::from([1.0, 0.0, 0.0]));
+            let y = self.rotate_vector(Vec3::from([0.0, 1.0, 0.0]));
+            let z = self.rotate_vector(Vec3::from([0.0, 0.0, 1.0]));
+
+            Mat3x3::from([
+                x.x_1, y.x_1, z.x_1,
+                x.x_2, y.x_2, z.x_2,
+                x.x_3, y.x_3, z.x_3,
+            ])
+        }
+
+        // Spherical interpolation along the shorter arc, t = 0 gives self and t = 1 gives target.
+        pub fn slerp(self, target: Quaternion, t: f32) -> Self {
+            let mut target = target;
+            let mut cos_angle = self.dot(target);
+
+            // q and -q are the same rotation
+            if cos_angle < 0.0 {
+                target = Quaternion { r: -target.r, i: -target.i, j: -target.j, k: -target.k };
+                cos_angle = -cos_angle;
+            }
+
+            let (weight_self, weight_target) = if cos_angle > 0.9995 {
+                // Nearly identical, a linear interpolation avoids dividing by sin(0)
+                (1.0 - t, t)
+            } else {
+                let angle = cos_angle.acos();
+                let sin_angle = angle.sin();
+                (((1.0 - t) * angle).sin() / sin_angle, (t * angle).sin() / sin_angle)
+            };
+
+            Quaternion {
+                r: self.r * weight_self + target.r * weight_target,
+                i: self.i * weight_self + target.i * weight_target,
+                j: self.j * weight_self + target.j * weight_target,
+                k: self.k * weight_self + target.k * weight_target,
+            }.normalize()
+        }
+
+        pub fn conjugate(self) -> Self {
+            Self {
+                r: self.r,
+                i: -self.i,
+                j: -self.j,
+                k: -self.k,
+            }
+        }
+
+        pub fn rotate(point: Vec3, axis: Vec3, angle: f32) -> Vec3 {
+            Self::from_axis_angle(axis, angle).rotate_vector(point)
+        }
+
         pub fn rotate_offset(point: Vec3, axis: Vec3, angle: f32, offset: Vec3) -> Vec3 {
             Self::rotate(point - offset, axis, angle) + offset
         }
@@ -1242,4 +1424,102 @@ pub mod matrix {
             }
         }
     }
+
+    // Distances are measured in multiples of direction, new normalizes it
+    #[derive(Debug, Clone, Copy)]
+    pub struct Ray {
+        pub origin: Vec3,
+        pub direction: Vec3,
+    }
+
+    impl Ray {
+        pub fn new(origin: Vec3, direction: Vec3) -> Self {
+            Self {
+                origin,
+                direction: direction.normalize(),
+            }
+        }
+
+        pub fn at(self, distance: f32) -> Vec3 {
+            self.origin + self.direction * distance
+        }
+
+        // Möller–Trumbore, hits both sides of the triangle
+        pub fn intersect_triangle(self, triangle: [Vec3; 3]) -> Option<f32> {
+            let edge_1 = triangle[1] - triangle[0];
+            let edge_2 = triangle[2] - triangle[0];
+
+            let p = self.direction % edge_2;
+            let determinant = edge_1 * p;
+            if determinant.abs() < 1e-8 {
+                // Parallel to the triangle
+                return None;
+            }
+
+            let t = self.origin - triangle[0];
+            let u = (t * p) / determinant;
+            if !(0.0..=1.0).contains(&u) {
+                return None;
+            }
+
+            let q = t % edge_1;
+            let v = (self.direction * q) / determinant;
+            if v < 0.0 || u + v > 1.0 {
+                return None;
+            }
+
+            let distance = (edge_2 * q) / determinant;
+            (distance >= 0.0).then_some(distance)
+        }
+
+        // Slab test, zero when the origin is inside the box
+        pub fn intersect_aabb(self, min: Vec3, max: Vec3) -> Option<f32> {
+            let origin = self.origin.to_array();
+            let direction = self.direction.to_array();
+            let (min, max) = (min.to_array(), max.to_array());
+
+            let mut near = 0.0_f32;
+            let mut far = f32::INFINITY;
+
+            for axis in 0..3 {
+                if direction[axis] == 0.0 {
+                    if origin[axis] < min[axis] || origin[axis] > max[axis] {
+                        return None;
+                    }
+                    continue;
+                }
+
+                let a = (min[axis] - origin[axis]) / direction[axis];
+                let b = (max[axis] - origin[axis]) / direction[axis];
+                near = near.max(a.min(b));
+                far = far.min(a.max(b));
+
+                if near > far {
+                    return None;
+                }
+            }
+
+            Some(near)
+        }
+
+        // Zero when the origin is inside the sphere
+        pub fn intersect_sphere(self, center: Vec3, radius: f32) -> Option<f32> {
+            let offset = self.origin - center;
+            let a = self.direction * self.direction;
+            let b = offset * self.direction;
+            let c = offset * offset - radius * radius;
+
+            if c <= 0.0 {
+                return Some(0.0);
+            }
+
+            let discriminant = b * b - a * c;
+            if a == 0.0 || discriminant < 0.0 {
+                return None;
+            }
+
+            let distance = (-b - discriminant.sqrt()) / a;
+            (distance >= 0.0).then_some(distance)
+        }
+    }
 }
@@ -0,0 +1,332 @@
+// On-screen frame statistics.
+//
+// Draws the numbers of an FpsCounter and a graph of the recent frame times in
+// the top left corner, on top of the finished frame. Text uses a built-in 3x5
+// pixel font, so the overlay needs no font files or textures.
+
+pub mod overlay {
+    use crate::fps::fps::FpsCounter;
+
+    const PIXEL_SIZE: f32 = 2.0;
+    const MARGIN: f32 = 8.0;
+    const LINE_HEIGHT: f32 = 7.0 * PIXEL_SIZE;
+    const GRAPH_HEIGHT: f32 = 40.0;
+    const GRAPH_FRAMES: usize = 120;
+    // Frame time at the top of the graph
+    const GRAPH_MAX_TIME: f32 = 1.0 / 20.0;
+
+    const BACKGROUND: [f32; 4] = [0.0, 0.0, 0.0, 0.6];
+    const TEXT: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+    const GOOD: [f32; 4] = [0.2, 0.9, 0.2, 1.0];
+    const SLOW: [f32; 4] = [1.0, 0.8, 0.1, 1.0];
+    const BAD: [f32; 4] = [1.0, 0.2, 0.1, 1.0];
+    const GUIDE: [f32; 4] = [1.0, 1.0, 1.0, 0.3];
+
+    #[repr(C)]
+    #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+    struct OverlayVertex {
+        position: [f32; 2],
+        color: [f32; 4],
+    }
+
+    impl OverlayVertex {
+        fn descriptor() -> wgpu::VertexBufferLayout<'static> {
+            use wgpu::{
+                VertexAttribute,
+                BufferAddress,
+                VertexFormat,
+                VertexStepMode,
+                VertexBufferLayout,
+            };
+
+            VertexBufferLayout {
+                array_stride: size_of::<OverlayVertex>() as BufferAddress,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &[
+                    VertexAttribute {
+                        offset: 0,
+                        shader_location: 0,
+                        format: VertexFormat::Float32x2,
+                    },
+                    VertexAttribute {
+                        offset: size_of::<[f32; 2]>() as BufferAddress,
+                        shader_location: 1,
+                        format: VertexFormat::Float32x4,
+                    },
+                ]
+            }
+        }
+    }
+
+    pub struct Overlay {
+        pub visible: bool,
+        format: wgpu::TextureFormat,
+        pipeline: wgpu::RenderPipeline,
+        vertex_buffer: wgpu::Buffer,
+        vertex_capacity: usize,
+        vertex_count: u32,
+    }
+
+    impl Overlay {
+        pub fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+            let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some("Overlay shader"),
+                source: wgpu::ShaderSource::Wgsl(include_str!("./overlay.wgsl").into()),
+            });
+
+            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Overlay pipeline layout"),
+                bind_group_layouts: &[],
+                push_constant_ranges: &[],
+            });
+
+            let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Overlay pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[
+                        OverlayVertex::descriptor(),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: Some("fs_main"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            });
+
+            let vertex_capacity = 4096;
+            let vertex_buffer = Self::create_vertex_buffer(device, vertex_capacity);
+
+            Self {
+                visible: false,
+                format,
+                pipeline,
+                vertex_buffer,
+                vertex_capacity,
+                vertex_count: 0,
+            }
+        }
+
+        pub fn format(&self) -> wgpu::TextureFormat {
+            self.format
+        }
+
+        fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Overlay vertex buffer"),
+                size: (capacity * size_of::<OverlayVertex>()) as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        }
+
+        // Rebuilds the geometry from the current statistics, width and height are the frame size in pixels.
+        pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, fps_counter: &FpsCounter, width: u32, height: u32) {
+            if !self.visible {
+                self.vertex_count = 0;
+                return;
+            }
+
+            let stats = fps_counter.stats();
+            let lines = [
+                format!("FPS {:.1}", stats.fps),
+                format!("AVG {:.2} MS", stats.average_frame_time * 1000.0),
+                format!("MIN {:.2} MAX {:.2}", stats.min_frame_time * 1000.0, stats.max_frame_time * 1000.0),
+                format!("1% LOW {:.1} 0.1% LOW {:.1}", stats.low_1_percent, stats.low_0_1_percent),
+                format!("CPU {:.2} GPU {:.2} MS", stats.average_update_time * 1000.0, stats.average_submit_time * 1000.0),
+            ];
+
+            let mut builder = QuadBuilder::new(width as f32, height as f32);
+
+            let text_width = lines.iter().map(|line| line.len()).max().unwrap_or(0) as f32 * 4.0 * PIXEL_SIZE;
+            let panel_width = text_width.max(GRAPH_FRAMES as f32 * 2.0) + 2.0 * MARGIN;
+            let panel_height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT + 3.0 * MARGIN;
+            builder.rect(0.0, 0.0, panel_width, panel_height, BACKGROUND);
+
+            for (i, line) in lines.iter().enumerate() {
+                builder.text(MARGIN, MARGIN + i as f32 * LINE_HEIGHT, line, TEXT);
+            }
+
+            // Frame time graph, newest frame on the right
+            let graph_top = panel_height - MARGIN - GRAPH_HEIGHT;
+            let graph_bottom = graph_top + GRAPH_HEIGHT;
+            let bar_width = (panel_width - 2.0 * MARGIN) / GRAPH_FRAMES as f32;
+
+            for target in [1.0 / 60.0, 1.0 / 30.0] {
+                let y = graph_bottom - GRAPH_HEIGHT * target / GRAPH_MAX_TIME;
+                builder.rect(MARGIN, y, panel_width - 2.0 * MARGIN, 1.0, GUIDE);
+            }
+
+            let skip = fps_counter.samples().len().saturating_sub(GRAPH_FRAMES);
+            let offset = GRAPH_FRAMES.saturating_sub(fps_counter.samples().len());
+
+            for (i, sample) in fps_counter.samples().skip(skip).enumerate() {
+                let bar_height = GRAPH_HEIGHT * (sample.frame_time / GRAPH_MAX_TIME).min(1.0);
+                let color = match sample.frame_time {
+                    time if time <= 1.0 / 55.0 => GOOD,
+                    time if time <= 1.0 / 28.0 => SLOW,
+                    _ => BAD,
+                };
+
+                builder.rect(
+                    MARGIN + (offset + i) as f32 * bar_width,
+                    graph_bottom - bar_height,
+                    bar_width.max(1.0),
+                    bar_height,
+                    color,
+                );
+            }
+
+            let vertices = builder.vertices;
+
+            if vertices.len() > self.vertex_capacity {
+                self.vertex_capacity = vertices.len().next_power_of_two();
+                self.vertex_buffer = Self::create_vertex_buffer(device, self.vertex_capacity);
+            }
+
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+            self.vertex_count = vertices.len() as u32;
+        }
+
+        // Draws on top of the view without touching its contents.
+        pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, view: &wgpu::TextureView) {
+            if !self.visible || self.vertex_count == 0 {
+                return;
+            }
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                    depth_slice: None,
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+
+            render_pass.set_pipeline(&self.pipeline);
+            render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+            render_pass.draw(0..self.vertex_count, 0..1);
+        }
+    }
+
+    // Collects rectangles given in pixels as triangles in clip space
+    struct QuadBuilder {
+        width: f32,
+        height: f32,
+        vertices: Vec<OverlayVertex>,
+    }
+
+    impl QuadBuilder {
+        fn new(width: f32, height: f32) -> Self {
+            Self {
+                width: width.max(1.0),
+                height: height.max(1.0),
+                vertices: Vec::new(),
+            }
+        }
+
+        fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: [f32; 4]) {
+            let left = x / self.width * 2.0 - 1.0;
+            let right = (x + width) / self.width * 2.0 - 1.0;
+            let top = 1.0 - y / self.height * 2.0;
+            let bottom = 1.0 - (y + height) / self.height * 2.0;
+
+            for position in [
+                [left, top], [left, bottom], [right, bottom],
+                [left, top], [right, bottom], [right, top],
+            ] {
+                self.vertices.push(OverlayVertex { position, color });
+            }
+        }
+
+        fn text(&mut self, x: f32, y: f32, text: &str, color: [f32; 4]) {
+            for (i, character) in text.chars().enumerate() {
+                let glyph_x = x + i as f32 * 4.0 * PIXEL_SIZE;
+
+                for (row, bits) in glyph(character).iter().enumerate() {
+                    for column in 0..3 {
+                        if bits & (0b100 >> column) != 0 {
+                            self.rect(
+                                glyph_x + column as f32 * PIXEL_SIZE,
+                                y + row as f32 * PIXEL_SIZE,
+                                PIXEL_SIZE,
+                                PIXEL_SIZE,
+                                color,
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // 3x5 pixel glyphs, one row per entry, the highest of the three bits is the left pixel.
+    // Characters without a glyph are drawn as spaces.
+    fn glyph(character: char) -> [u8; 5] {
+        match character.to_ascii_uppercase() {
+            '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+            '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+            '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+            '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+            '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+            '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+            '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+            '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+            '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+            '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+            ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+            '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+            'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+            'C' => [0b111, 0b100, 0b100, 0b100, 0b111],
+            'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+            'G' => [0b111, 0b100, 0b101, 0b101, 0b111],
+            'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+            'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+            'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+            'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+            'O' => [0b111, 0b101, 0b101, 0b101, 0b111],
+            'P' => [0b111, 0b101, 0b111, 0b100, 0b100],
+            'S' => [0b111, 0b100, 0b111, 0b001, 0b111],
+            'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+            'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+            'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+            'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+            _ => [0; 5],
+        }
+    }
+}
@@ -0,0 +1,703 @@
+// Tree structure of PhysicsWorld:
+//
+// * bodies: BTreeMap<MeshId, RigidBody>
+//   - mass: f32
+//   - inertia: Mat3x3
+//   - center_of_mass: [f32; 3]
+//   - scale: [f32; 3]
+//   - position: [f32; 3]
+//   - orientation: Quaternion
+//   - linear_velocity: [f32; 3]
+//   - angular_velocity: [f32; 3]
+//   - restitution: f32
+//   - friction: f32
+//   - sleeping: bool
+// * gravity: [f32; 3]
+// * iterations: u32
+// * substeps: u32
+//
+// Rigid bodies move the meshes they belong to. Colliders without a body are
+// static. Ticks are split into substeps, so fast bodies do not sink deep into
+// each other before their contacts are found. Every substep the collisions are
+// updated, gravity is applied, the contacts are solved with sequential impulses
+// (normal impulses clamped to push only, friction clamped to the friction
+// cone), then positions are integrated and the mesh transforms are set. Bodies
+// that stay slow for sleep_delay seconds fall asleep and are treated as static
+// until something wakes them. Meshes with a body should not be attached to
+// scene graph nodes, both would set their transform.
+
+pub mod physics {
+    use std::collections::BTreeMap;
+
+    use crate::collision::collision::{CollisionWorld, Contact};
+    use crate::mesh::mesh::{MeshId, MeshRegistry};
+    use crate::object::object::Object;
+    use crate::object::object::gmlib::matrix::*;
+
+    // Contacts kept per pair, four points are enough to rest a face on
+    const MAX_CONTACTS: usize = 4;
+    // Share of the penetration removed per tick, and the penetration left alone
+    const BAUMGARTE: f32 = 0.2;
+    const PENETRATION_SLOP: f32 = 0.005;
+    // Slower impacts do not bounce, resting contacts would jitter otherwise
+    const RESTITUTION_THRESHOLD: f32 = 1.0;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct RigidBody {
+        mass: f32,
+        inverse_mass: f32,
+        // Around the center of mass, in object space
+        inertia: Mat3x3,
+        inverse_inertia: Mat3x3,
+        // Object space after scaling, including Object::position
+        center_of_mass: Vec3,
+        // Of the mesh transform, baked into the mass properties
+        scale: Vec3,
+        // World space position of the center of mass
+        pub position: Vec3,
+        pub orientation: Quaternion,
+        pub linear_velocity: Vec3,
+        // Axis times radians per second
+        pub angular_velocity: Vec3,
+        // 0 stops dead, 1 bounces back at full speed
+        pub restitution: f32,
+        pub friction: f32,
+        // Share of the velocity lost per second
+        pub linear_damping: f32,
+        pub angular_damping: f32,
+        sleeping: bool,
+        // How long the body has been slow enough to sleep
+        slow_time: f32,
+    }
+
+    impl RigidBody {
+        // Mass properties of the solid enclosed by the triangles, which have to form a
+        // closed mesh. Each triangle spans a tetrahedron with the origin, their signed
+        // volumes add up to the solid. None for open or flat meshes.
+        pub fn from_object(object: &Object, density: f32) -> Option<Self> {
+            Self::from_scaled_object(object, density, Vec3::from([1.0, 1.0, 1.0]))
+        }
+
+        // Like from_object for an object drawn with this scale, see scale_of().
+        pub fn from_scaled_object(object: &Object, density: f32, scale: Vec3) -> Option<Self> {
+            let mut volume = 0.0;
+            let mut first_moment = Vec3::from([0.0, 0.0, 0.0]);
+            let mut covariance = [[0.0_f32; 3]; 3];
+
+            let scaled = |position: Vec3| [position.x_1 * scale.x_1, position.x_2 * scale.x_2, position.x_3 * scale.x_3];
+
+            for triangle in object.triangles.iter() {
+                let [a, b, c] = triangle.vertices.map(|vertex| scaled(vertex.position + object.position));
+                // Six times the signed volume of the tetrahedron
+                let determinant = Vec3::from(a) * (Vec3::from(b) % Vec3::from(c));
+
+                volume += determinant / 6.0;
+                first_moment = first_moment + (Vec3::from(a) + Vec3::from(b) + Vec3::from(c)) * (determinant / 24.0);
+
+                for i in 0..3 {
+                    for j in 0..3 {
+                        let sum_i = a[i] + b[i] + c[i];
+                        let sum_j = a[j] + b[j] + c[j];
+                        covariance[i][j] += determinant / 120.0
+                            * (a[i] * a[j] + b[i] * b[j] + c[i] * c[j] + sum_i * sum_j);
+                    }
+                }
+            }
+
+            // The winding decides the sign, only the magnitude matters
+            let sign = volume.signum();
+            let volume = volume * sign;
+            if volume <= 1e-9 || density <= 0.0 {
+                return None;
+            }
+
+            let mass = density * volume;
+            let center_of_mass = first_moment * (sign / volume);
+            let center = center_of_mass.to_array();
+
+            // Move the covariance to the center of mass, then turn it into the inertia tensor
+            let covariance: [[f32; 3]; 3] = std::array::from_fn(|i| std::array::from_fn(|j| {
+                covariance[i][j] * sign * density - mass * center[i] * center[j]
+            }));
+            let trace = covariance[0][0] + covariance[1][1] + covariance[2][2];
+            let inertia = Mat3x3::from(std::array::from_fn(|index| {
+                let (i, j) = (index / 3, index % 3);
+                if i == j { trace - covariance[i][j] } else { -covariance[i][j] }
+            }));
+
+            Some(Self {
+                mass,
+                inverse_mass: 1.0 / mass,
+                inertia,
+                inverse_inertia: inertia.inverse(),
+                center_of_mass,
+                scale,
+                position: center_of_mass,
+                orientation: UNIT_QUATERNION,
+                linear_velocity: Vec3::from([0.0, 0.0, 0.0]),
+                angular_velocity: Vec3::from([0.0, 0.0, 0.0]),
+                restitution: 0.2,
+                friction: 0.6,
+                linear_damping: 0.05,
+                angular_damping: 0.1,
+                sleeping: false,
+                slow_time: 0.0,
+            })
+        }
+
+        pub fn mass(&self) -> f32 {
+            self.mass
+        }
+
+        pub fn inertia(&self) -> Mat3x3 {
+            self.inertia
+        }
+
+        pub fn center_of_mass(&self) -> Vec3 {
+            self.center_of_mass
+        }
+
+        pub fn scale(&self) -> Vec3 {
+            self.scale
+        }
+
+        // Scale along the axes of a transform without shear, mirroring ones get a
+        // negative x scale.
+        pub fn scale_of(transform: Mat4x4) -> Vec3 {
+            let rows = transform.to_array();
+            let column = |j: usize| Vec3::from([rows[0][j], rows[1][j], rows[2][j]]);
+            let (x, y, z) = (column(0), column(1), column(2));
+            let mirrored = transform.normal_matrix().determinant() < 0.0;
+
+            Vec3::from([
+                if mirrored { -x.magnitude() } else { x.magnitude() },
+                y.magnitude(),
+                z.magnitude(),
+            ])
+        }
+
+        pub fn is_sleeping(&self) -> bool {
+            self.sleeping
+        }
+
+        pub fn wake(&mut self) {
+            self.sleeping = false;
+            self.slow_time = 0.0;
+        }
+
+        // Places the body like a mesh with this world transform. The scale of the transform
+        // is divided out, the body keeps the scale it was built with.
+        pub fn set_transform(&mut self, transform: Mat4x4) {
+            let column = |j: usize| Vec3::from(std::array::from_fn(|i| transform.to_array()[i][j]));
+            let scale = Self::scale_of(transform);
+
+            self.orientation = Quaternion::from_axes(
+                column(0) / scale.x_1,
+                column(1) / scale.x_2,
+                column(2) / scale.x_3,
+            );
+            self.position = self.orientation.rotate_vector(self.center_of_mass) + column(3);
+            self.wake();
+        }
+
+        // World transform for the mesh of the body, including its scale
+        pub fn transform(&self) -> Mat4x4 {
+            let rotation = self.orientation.to_mat3x3();
+            let translation = self.position - rotation * self.center_of_mass;
+            let [row_1, row_2, row_3] = rotation.to_array();
+            let [x, y, z] = self.scale.to_array();
+
+            Mat4x4::from([
+                row_1[0] * x, row_1[1] * y, row_1[2] * z, translation.x_1,
+                row_2[0] * x, row_2[1] * y, row_2[2] * z, translation.x_2,
+                row_3[0] * x, row_3[1] * y, row_3[2] * z, translation.x_3,
+                0.0,          0.0,          0.0,          1.0,
+            ])
+        }
+
+        // Inverse inertia tensor in world space
+        pub fn inverse_inertia_world(&self) -> Mat3x3 {
+            let rotation = self.orientation.to_mat3x3();
+            rotation * self.inverse_inertia * rotation.transpose()
+        }
+
+        // Velocity of the point of the body that is currently at point
+        pub fn velocity_at(&self, point: Vec3) -> Vec3 {
+            self.linear_velocity + self.angular_velocity % (point - self.position)
+        }
+
+        // Instant change of momentum at a world space point
+        pub fn apply_impulse(&mut self, impulse: Vec3, point: Vec3) {
+            self.linear_velocity = self.linear_velocity + impulse * self.inverse_mass;
+            self.angular_velocity = self.angular_velocity
+                + self.inverse_inertia_world() * ((point - self.position) % impulse);
+            self.wake();
+        }
+    }
+
+    pub struct PhysicsWorld {
+        bodies: BTreeMap<MeshId, RigidBody>,
+        pub gravity: Vec3,
+        // Solver passes over all contacts per tick, more settle stacks better
+        pub iterations: u32,
+        // Collision updates and solves per tick
+        pub substeps: u32,
+        // Bodies slower than this, in units (or radians) per second, may fall asleep
+        pub sleep_velocity: f32,
+        // Seconds a body has to stay slow before it sleeps
+        pub sleep_delay: f32,
+    }
+
+    impl Default for PhysicsWorld {
+        fn default() -> Self {
+            Self {
+                bodies: BTreeMap::new(),
+                gravity: Vec3::from([0.0, -9.81, 0.0]),
+                iterations: 10,
+                substeps: 4,
+                sleep_velocity: 0.1,
+                sleep_delay: 0.5,
+            }
+        }
+    }
+
+    // Per tick copy of the awake bodies the solver works on
+    struct SolverBody {
+        position: Vec3,
+        inverse_mass: f32,
+        inverse_inertia: Mat3x3,
+        linear_velocity: Vec3,
+        angular_velocity: Vec3,
+    }
+
+    impl SolverBody {
+        fn velocity_at(&self, offset: Vec3) -> Vec3 {
+            self.linear_velocity + self.angular_velocity % offset
+        }
+
+        fn apply_impulse(&mut self, impulse: Vec3, offset: Vec3) {
+            self.linear_velocity = self.linear_velocity + impulse * self.inverse_mass;
+            self.angular_velocity = self.angular_velocity + self.inverse_inertia * (offset % impulse);
+        }
+
+        // Inverse mass seen by an impulse along direction at offset from the center
+        fn effective_inverse_mass(&self, offset: Vec3, direction: Vec3) -> f32 {
+            self.inverse_mass + direction * ((self.inverse_inertia * (offset % direction)) % offset)
+        }
+    }
+
+    struct ContactConstraint {
+        // None for static and sleeping bodies
+        a: Option<usize>,
+        b: Option<usize>,
+        // Contact point relative to the centers of mass
+        offset_a: Vec3,
+        offset_b: Vec3,
+        // Points from b towards a
+        normal: Vec3,
+        tangents: [Vec3; 2],
+        normal_mass: f32,
+        tangent_mass: [f32; 2],
+        // Target separating velocity from restitution and penetration
+        bias: f32,
+        friction: f32,
+        normal_impulse: f32,
+        tangent_impulse: [f32; 2],
+    }
+
+    impl PhysicsWorld {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Replaces any body the mesh already had
+        pub fn add(&mut self, id: MeshId, body: RigidBody) {
+            self.bodies.insert(id, body);
+        }
+
+        // Wakes all bodies, they may have rested on the removed one
+        pub fn remove(&mut self, id: MeshId) -> Option<RigidBody> {
+            let body = self.bodies.remove(&id);
+            if body.is_some() {
+                self.wake_all();
+            }
+
+            body
+        }
+
+        pub fn get(&self, id: MeshId) -> Option<&RigidBody> {
+            self.bodies.get(&id)
+        }
+
+        // Wakes the body, changes to it would go unnoticed while it sleeps
+        pub fn get_mut(&mut self, id: MeshId) -> Option<&mut RigidBody> {
+            let body = self.bodies.get_mut(&id)?;
+            body.wake();
+
+            Some(body)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (MeshId, &RigidBody)> {
+            self.bodies.iter().map(|(id, body)| (*id, body))
+        }
+
+        pub fn len(&self) -> usize {
+            self.bodies.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.bodies.is_empty()
+        }
+
+        pub fn wake_all(&mut self) {
+            for body in self.bodies.values_mut() {
+                body.wake();
+            }
+        }
+
+        // Advances the bodies by one tick and moves their meshes. The collisions are
+        // updated before every substep, not after the last one.
+        pub fn step(&mut self, collisions: &mut CollisionWorld, meshes: &mut MeshRegistry, delta_time: f32) {
+            self.bodies.retain(|id, _| meshes.get(*id).is_some());
+
+            if delta_time <= 0.0 {
+                return;
+            }
+
+            let substeps = self.substeps.max(1);
+            for _ in 0..substeps {
+                collisions.update(meshes);
+                self.substep(collisions, meshes, delta_time / substeps as f32);
+            }
+        }
+
+        fn substep(&mut self, collisions: &CollisionWorld, meshes: &mut MeshRegistry, delta_time: f32) {
+            self.wake_touched(collisions);
+
+            // Awake bodies get a solver slot
+            let mut slots: BTreeMap<MeshId, usize> = BTreeMap::new();
+            let mut solver_bodies = Vec::new();
+
+            for (&id, body) in self.bodies.iter().filter(|(_, body)| !body.sleeping) {
+                let damping = |rate: f32| (1.0 - rate * delta_time).max(0.0);
+
+                slots.insert(id, solver_bodies.len());
+                solver_bodies.push(SolverBody {
+                    position: body.position,
+                    inverse_mass: body.inverse_mass,
+                    inverse_inertia: body.inverse_inertia_world(),
+                    linear_velocity: (body.linear_velocity + self.gravity * delta_time) * damping(body.linear_damping),
+                    angular_velocity: body.angular_velocity * damping(body.angular_damping),
+                });
+            }
+
+            let mut constraints = Vec::new();
+            for pair in collisions.pairs() {
+                let a = slots.get(&pair.a).copied();
+                let b = slots.get(&pair.b).copied();
+                if a.is_none() && b.is_none() {
+                    continue;
+                }
+
+                let material = |id: MeshId| self.bodies.get(&id).map(|body| (body.friction, body.restitution));
+                let (friction, restitution) = match (material(pair.a), material(pair.b)) {
+                    (Some((friction_a, restitution_a)), Some((friction_b, restitution_b))) =>
+                        ((friction_a * friction_b).sqrt(), restitution_a.max(restitution_b)),
+                    (Some(material), None) | (None, Some(material)) => material,
+                    (None, None) => continue,
+                };
+
+                for contact in reduce_contacts(&pair.contacts) {
+                    constraints.push(self.constraint(&solver_bodies, a, b, &contact, friction, restitution, delta_time));
+                }
+            }
+
+            for _ in 0..self.iterations {
+                for constraint in constraints.iter_mut() {
+                    solve_contact(&mut solver_bodies, constraint);
+                }
+            }
+
+            for (&id, &slot) in slots.iter() {
+                let solved = &solver_bodies[slot];
+                let body = self.bodies.get_mut(&id).unwrap();
+
+                body.linear_velocity = solved.linear_velocity;
+                body.angular_velocity = solved.angular_velocity;
+                body.position = body.position + body.linear_velocity * delta_time;
+
+                let angular_speed = body.angular_velocity.magnitude();
+                if angular_speed > 1e-6 {
+                    let turn = Quaternion::from_axis_angle(body.angular_velocity, angular_speed * delta_time);
+                    body.orientation = (turn * body.orientation).normalize();
+                }
+
+                let speed = body.linear_velocity.magnitude().max(angular_speed);
+                if speed < self.sleep_velocity {
+                    body.slow_time += delta_time;
+                } else {
+                    body.slow_time = 0.0;
+                }
+
+                if body.slow_time >= self.sleep_delay {
+                    body.sleeping = true;
+                    body.linear_velocity = Vec3::from([0.0, 0.0, 0.0]);
+                    body.angular_velocity = Vec3::from([0.0, 0.0, 0.0]);
+                }
+
+                meshes.set_transform(id, body.transform());
+            }
+        }
+
+        // Sleeping bodies touched by a moving body have to take part again
+        fn wake_touched(&mut self, collisions: &CollisionWorld) {
+            let is_moving = |body: &RigidBody| !body.sleeping
+                && body.linear_velocity.magnitude().max(body.angular_velocity.magnitude()) >= self.sleep_velocity;
+
+            let woken: Vec<MeshId> = collisions.pairs()
+                .iter()
+                .filter_map(|pair| {
+                    let a = self.bodies.get(&pair.a)?;
+                    let b = self.bodies.get(&pair.b)?;
+
+                    if a.sleeping && is_moving(b) {
+                        Some(pair.a)
+                    } else if b.sleeping && is_moving(a) {
+                        Some(pair.b)
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            for id in woken {
+                if let Some(body) = self.bodies.get_mut(&id) {
+                    body.wake();
+                }
+            }
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn constraint(
+            &self,
+            bodies: &[SolverBody],
+            a: Option<usize>,
+            b: Option<usize>,
+            contact: &Contact,
+            friction: f32,
+            restitution: f32,
+            delta_time: f32,
+        ) -> ContactConstraint {
+            let zero = Vec3::from([0.0, 0.0, 0.0]);
+            let offset_a = a.map_or(zero, |a| contact.point - bodies[a].position);
+            let offset_b = b.map_or(zero, |b| contact.point - bodies[b].position);
+            let normal = contact.normal;
+
+            let effective_inverse_mass = |direction: Vec3| {
+                a.map_or(0.0, |a| bodies[a].effective_inverse_mass(offset_a, direction))
+                    + b.map_or(0.0, |b| bodies[b].effective_inverse_mass(offset_b, direction))
+            };
+            let mass = |direction: Vec3| {
+                let inverse = effective_inverse_mass(direction);
+                if inverse > 0.0 { 1.0 / inverse } else { 0.0 }
+            };
+
+            let relative_velocity = a.map_or(zero, |a| bodies[a].velocity_at(offset_a))
+                - b.map_or(zero, |b| bodies[b].velocity_at(offset_b));
+            let normal_velocity = relative_velocity * normal;
+
+            // Friction acts against the sliding direction, any tangent will do when resting
+            let sliding = relative_velocity - normal * normal_velocity;
+            let tangent = if sliding.magnitude() > 1e-6 {
+                sliding.normalize()
+            } else if normal.x_1.abs() < 0.9 {
+                (normal % Vec3::from([1.0, 0.0, 0.0])).normalize()
+            } else {
+                (normal % Vec3::from([0.0, 1.0, 0.0])).normalize()
+            };
+            let tangents = [tangent, normal % tangent];
+
+            let bounce = if normal_velocity < -RESTITUTION_THRESHOLD { -restitution * normal_velocity } else { 0.0 };
+            let push_out = BAUMGARTE / delta_time * (contact.depth - PENETRATION_SLOP).max(0.0);
+
+            ContactConstraint {
+                a,
+                b,
+                offset_a,
+                offset_b,
+                normal,
+                tangents,
+                normal_mass: mass(normal),
+                tangent_mass: tangents.map(mass),
+                bias: bounce.max(push_out),
+                friction,
+                normal_impulse: 0.0,
+                tangent_impulse: [0.0, 0.0],
+            }
+        }
+    }
+
+    fn relative_velocity(bodies: &[SolverBody], constraint: &ContactConstraint) -> Vec3 {
+        let zero = Vec3::from([0.0, 0.0, 0.0]);
+
+        constraint.a.map_or(zero, |a| bodies[a].velocity_at(constraint.offset_a))
+            - constraint.b.map_or(zero, |b| bodies[b].velocity_at(constraint.offset_b))
+    }
+
+    fn apply(bodies: &mut [SolverBody], constraint: &ContactConstraint, impulse: Vec3) {
+        if let Some(a) = constraint.a {
+            bodies[a].apply_impulse(impulse, constraint.offset_a);
+        }
+        if let Some(b) = constraint.b {
+            bodies[b].apply_impulse(-impulse, constraint.offset_b);
+        }
+    }
+
+    fn solve_contact(bodies: &mut [SolverBody], constraint: &mut ContactConstraint) {
+        // Friction first, it is limited by the normal impulse of the last pass
+        for i in 0..2 {
+            let tangent = constraint.tangents[i];
+            let velocity = relative_velocity(bodies, constraint) * tangent;
+            let limit = constraint.friction * constraint.normal_impulse;
+
+            let total = (constraint.tangent_impulse[i] - velocity * constraint.tangent_mass[i]).clamp(-limit, limit);
+            let change = total - constraint.tangent_impulse[i];
+            constraint.tangent_impulse[i] = total;
+
+            apply(bodies, constraint, tangent * change);
+        }
+
+        // Accumulated impulse may only push, but single passes may take back earlier ones
+        let velocity = relative_velocity(bodies, constraint) * constraint.normal;
+        let total = (constraint.normal_impulse + (constraint.bias - velocity) * constraint.normal_mass).max(0.0);
+        let change = total - constraint.normal_impulse;
+        constraint.normal_impulse = total;
+
+        apply(bodies, constraint, constraint.normal * change);
+    }
+
+    // Keeps the deepest contact and the ones spanning the largest area around it
+    fn reduce_contacts(contacts: &[Contact]) -> Vec<Contact> {
+        if contacts.len() <= MAX_CONTACTS {
+            return contacts.to_vec();
+        }
+
+        let deepest = contacts.iter()
+            .copied()
+            .max_by(|a, b| a.depth.total_cmp(&b.depth))
+            .unwrap();
+        let mut kept = vec![deepest];
+
+        while kept.len() < MAX_CONTACTS {
+            // The contact furthest from all kept ones spreads the support the most
+            let distance = |contact: &Contact| kept.iter()
+                .map(|kept| (kept.point - contact.point).magnitude())
+                .fold(f32::MAX, f32::min);
+
+            let next = contacts.iter()
+                .copied()
+                .max_by(|a, b| distance(a).total_cmp(&distance(b)))
+                .unwrap();
+
+            if distance(&next) <= 1e-4 {
+                break;
+            }
+            kept.push(next);
+        }
+
+        kept
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::object::object::{Triangle, Vertex};
+
+        // Skipped on machines without any adapter, meshes live on the GPU
+        fn device() -> Option<wgpu::Device> {
+            let instance = wgpu::Instance::default();
+            let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default())).ok()?;
+            let (device, _) = pollster::block_on(adapter.request_device(&wgpu::DeviceDescriptor::default())).ok()?;
+
+            Some(device)
+        }
+
+        // Unit cube around the origin with its faces pointing outwards
+        fn cube() -> Object {
+            let corner = |i: usize| Vec3::from([
+                if i & 1 == 0 { -0.5 } else { 0.5 },
+                if i & 2 == 0 { -0.5 } else { 0.5 },
+                if i & 4 == 0 { -0.5 } else { 0.5 },
+            ]);
+            let faces = [[0, 1, 3, 2], [4, 6, 7, 5], [0, 4, 5, 1], [2, 3, 7, 6], [0, 2, 6, 4], [1, 5, 7, 3]];
+
+            let mut triangles = Vec::new();
+            for [a, b, c, d] in faces {
+                for indices in [[a, b, c], [a, c, d]] {
+                    let mut triangle = Triangle::new(indices.map(|i| Vertex::new(corner(i).to_array(), [1.0; 4])));
+                    let center = corner(a) + corner(c);
+                    if triangle.face_normal() * center < 0.0 {
+                        triangle.vertices.swap(1, 2);
+                    }
+                    triangles.push(triangle);
+                }
+            }
+
+            Object::new([0.0, 0.0, 0.0], triangles, true)
+        }
+
+        fn scaled(scale: [f32; 3], y: f32) -> Mat4x4 {
+            Mat4x4::from([
+                scale[0], 0.0,      0.0,      0.0,
+                0.0,      scale[1], 0.0,      y,
+                0.0,      0.0,      scale[2], 0.0,
+                0.0,      0.0,      0.0,      1.0,
+            ])
+        }
+
+        #[test]
+        fn scaled_boxes_stack_and_sleep() {
+            let Some(device) = device() else {
+                eprintln!("Skipping, no adapter");
+                return;
+            };
+
+            let mut meshes = MeshRegistry::new(&device);
+            let mut collisions = CollisionWorld::new();
+            let mut physics = PhysicsWorld::new();
+
+            // Static ground with its top at y = 0
+            let ground = meshes.add(&device, cube());
+            meshes.set_transform(ground, scaled([10.0, 1.0, 10.0], -0.5));
+
+            // A flat slab 0.5 high with a taller, narrower box on it, both dropped from a little above
+            let mut add_box = |scale: [f32; 3], y: f32| {
+                let id = meshes.add(&device, cube());
+                let transform = scaled(scale, y);
+                meshes.set_transform(id, transform);
+
+                let mut body = RigidBody::from_scaled_object(&cube(), 1.0, RigidBody::scale_of(transform)).unwrap();
+                body.set_transform(transform);
+                physics.add(id, body);
+                id
+            };
+            let bottom = add_box([2.0, 0.5, 2.0], 0.35);
+            let top = add_box([1.0, 1.5, 1.0], 1.6);
+
+            assert!((physics.get(bottom).unwrap().mass() - 2.0).abs() < 1e-4);
+            assert!((physics.get(top).unwrap().mass() - 1.5).abs() < 1e-4);
+
+            for _ in 0..600 {
+                physics.step(&mut collisions, &mut meshes, 1.0 / 60.0);
+            }
+
+            for (id, height) in [(bottom, 0.25), (top, 1.25)] {
+                let body = physics.get(id).unwrap();
+
+                assert!((body.position.x_2 - height).abs() < 0.02, "{:?} rests at {}, expected {}", id, body.position.x_2, height);
+                assert!(body.is_sleeping(), "{:?} is still awake", id);
+                assert!((body.scale().x_2 - body.transform().to_array()[1][1]).abs() < 1e-3);
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+// Tree structure of IdBuffer:
+//
+// * pipelines: PickPipelines
+//   - culled: wgpu::RenderPipeline
+//   - double_sided: wgpu::RenderPipeline
+// * target: Option<IdTarget>
+//   - texture: wgpu::Texture
+//   - view: wgpu::TextureView
+//   - depth_texture: Texture
+//   - size: [u32; 2]
+// * readback: wgpu::Buffer
+//
+// Picking finds the object under a pixel. PickMode::Ray casts a ray from the
+// camera through the pixel against the triangles of the objects.
+// PickMode::IdBuffer draws the scene again with the pick id of every mesh as
+// its color and reads the pixel back, which matches the rendered image exactly
+// but waits for the GPU. Only the picked pixel is drawn, the rest is cut off by
+// a scissor rect. Masked materials cut out the same fragments as in the
+// rendered image, and double-sided ones can be picked from behind.
+
+pub mod picking {
+    use crate::{DepthSettings, Instance, Vertex};
+    use crate::material::material::Material;
+    use crate::texture::texture::Texture;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+    pub enum PickMode {
+        #[default]
+        Ray,
+        IdBuffer,
+    }
+
+    struct IdTarget {
+        texture: wgpu::Texture,
+        view: wgpu::TextureView,
+        depth_texture: Texture,
+        size: [u32; 2],
+    }
+
+    pub struct PickPipelines {
+        culled: wgpu::RenderPipeline,
+        double_sided: wgpu::RenderPipeline,
+    }
+
+    impl PickPipelines {
+        pub fn get(&self, material: &Material) -> &wgpu::RenderPipeline {
+            if material.double_sided { &self.double_sided } else { &self.culled }
+        }
+    }
+
+    pub struct IdBuffer {
+        pipelines: PickPipelines,
+        // Created on the first pick, and again whenever the size changes
+        target: Option<IdTarget>,
+        readback: wgpu::Buffer,
+    }
+
+    impl IdBuffer {
+        pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+
+        // The layout, shader and depth settings are the ones of the render pipeline,
+        // the shader has to provide fs_pick.
+        pub fn new(
+            device: &wgpu::Device,
+            layout: &wgpu::PipelineLayout,
+            shader: &wgpu::ShaderModule,
+            depth_settings: DepthSettings,
+        ) -> Self {
+            let pipelines = PickPipelines {
+                culled: Self::create_pipeline(device, layout, shader, depth_settings, false),
+                double_sided: Self::create_pipeline(device, layout, shader, depth_settings, true),
+            };
+
+            let readback = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pick readback buffer"),
+                size: size_of::<u32>() as wgpu::BufferAddress,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            });
+
+            Self {
+                pipelines,
+                target: None,
+                readback,
+            }
+        }
+
+        // Draws the frame of the given config into the ID buffer and returns the pick id
+        // at pixel, 0 where nothing was drawn. draw has to set the bind groups the render
+        // pipeline expects and draw the meshes with the given pipelines.
+        pub fn read(
+            &mut self,
+            device: &wgpu::Device,
+            queue: &wgpu::Queue,
+            config: &wgpu::SurfaceConfiguration,
+            pixel: [u32; 2],
+            draw: impl FnOnce(&mut wgpu::RenderPass, &PickPipelines),
+        ) -> anyhow::Result<u32> {
+            let size = [config.width.max(1), config.height.max(1)];
+            if pixel[0] >= size[0] || pixel[1] >= size[1] {
+                return Ok(0);
+            }
+
+            if self.target.as_ref().is_none_or(|target| target.size != size) {
+                self.target = Some(Self::create_target(device, config, size));
+            }
+            let target = self.target.as_ref().unwrap();
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Pick Encoder"),
+            });
+
+            {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Pick Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: &target.view,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                        depth_slice: None,
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: &target.depth_texture.view,
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Discard,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    occlusion_query_set: None,
+                    timestamp_writes: None,
+                });
+
+                render_pass.set_scissor_rect(pixel[0], pixel[1], 1, 1);
+                draw(&mut render_pass, &self.pipelines);
+            }
+
+            encoder.copy_texture_to_buffer(
+                wgpu::TexelCopyTextureInfo {
+                    texture: &target.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: pixel[0],
+                        y: pixel[1],
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::TexelCopyBufferInfo {
+                    buffer: &self.readback,
+                    layout: wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        // A single row needs no alignment
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+
+            queue.submit(std::iter::once(encoder.finish()));
+
+            let buffer_slice = self.readback.slice(..);
+            let (sender, receiver) = std::sync::mpsc::channel();
+            buffer_slice.map_async(wgpu::MapMode::Read, move |result| {
+                let _ = sender.send(result);
+            });
+            device.poll(wgpu::PollType::wait_indefinitely())?;
+            receiver.recv()??;
+
+            let pick_id = {
+                let data = buffer_slice.get_mapped_range();
+                u32::from_ne_bytes([data[0], data[1], data[2], data[3]])
+            };
+            self.readback.unmap();
+
+            Ok(pick_id)
+        }
+
+        fn create_pipeline(
+            device: &wgpu::Device,
+            layout: &wgpu::PipelineLayout,
+            shader: &wgpu::ShaderModule,
+            depth_settings: DepthSettings,
+            double_sided: bool,
+        ) -> wgpu::RenderPipeline {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Pick pipeline"),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: shader,
+                    entry_point: Some("vs_main"),
+                    buffers: &[
+                        Vertex::descriptor(),
+                        Instance::descriptor(),
+                    ],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: shader,
+                    entry_point: Some("fs_pick"),
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: Self::FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: if double_sided { None } else { Some(wgpu::Face::Back) },
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: depth_settings.write_enabled,
+                    depth_compare: depth_settings.compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        }
+
+        fn create_target(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, size: [u32; 2]) -> IdTarget {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("ID texture"),
+                size: wgpu::Extent3d {
+                    width: size[0],
+                    height: size[1],
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            });
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            IdTarget {
+                texture,
+                view,
+                depth_texture: Texture::create_depth_texture(device, config, "ID depth texture"),
+                size,
+            }
+        }
+    }
+}
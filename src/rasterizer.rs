@@ -0,0 +1,358 @@
+// Pure software rasterizer.
+//
+// Renders the same scene data as the wgpu path (Objects and a Camera) on the
+// CPU, following the pipeline state set up in State::new: counter-clockwise
+// front faces, back-face culling, a depth test and per-vertex colors. Colors
+// are stored linear and encoded to sRGB by to_image(), like the sRGB render
+// targets of the GPU path, so both outputs can be compared pixel by pixel.
+// Lighting and scene graph transforms are not modelled, compare against
+// scenes without lights and without nodes.
+
+pub mod rasterizer {
+    use crate::DepthSettings;
+    use crate::camera::camera::Camera;
+    use crate::object::object::Object;
+    use crate::object::object::gmlib::matrix::*;
+
+    pub struct Framebuffer {
+        pub width: u32,
+        pub height: u32,
+        pub color: Vec<[f32; 4]>,
+        pub depth: Vec<f32>,
+    }
+
+    impl Framebuffer {
+        pub fn new(width: u32, height: u32) -> Self {
+            let size = (width * height) as usize;
+
+            Self {
+                width,
+                height,
+                color: vec![[0.0, 0.0, 0.0, 1.0]; size],
+                depth: vec![1.0; size],
+            }
+        }
+
+        pub fn clear(&mut self, color: [f32; 4]) {
+            self.color.fill(color);
+            self.depth.fill(1.0);
+        }
+
+        pub fn pixel(&self, x: u32, y: u32) -> [f32; 4] {
+            self.color[(y * self.width + x) as usize]
+        }
+
+        pub fn to_image(&self) -> image::RgbaImage {
+            let mut image = image::RgbaImage::new(self.width, self.height);
+
+            for (pixel, color) in image.pixels_mut().zip(self.color.iter()) {
+                *pixel = image::Rgba([
+                    linear_to_srgb(color[0]),
+                    linear_to_srgb(color[1]),
+                    linear_to_srgb(color[2]),
+                    (color[3].clamp(0.0, 1.0) * 255.0).round() as u8,
+                ]);
+            }
+
+            image
+        }
+    }
+
+    fn linear_to_srgb(value: f32) -> u8 {
+        let value = value.clamp(0.0, 1.0);
+        let encoded = if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        };
+
+        (encoded * 255.0).round() as u8
+    }
+
+    #[derive(Copy, Clone, Debug)]
+    pub struct Rasterizer {
+        pub front_face: wgpu::FrontFace,
+        pub cull_mode: Option<wgpu::Face>,
+        pub depth_settings: DepthSettings,
+    }
+
+    impl Default for Rasterizer {
+        fn default() -> Self {
+            Self {
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                depth_settings: DepthSettings::default(),
+            }
+        }
+    }
+
+    // A vertex after the projection, before the perspective division
+    #[derive(Copy, Clone, Debug)]
+    struct ClipVertex {
+        position: Vec4,
+        color: Vec4,
+    }
+
+    impl ClipVertex {
+        fn lerp(self, other: ClipVertex, t: f32) -> ClipVertex {
+            ClipVertex {
+                position: self.position + (other.position - self.position) * t,
+                color: self.color + (other.color - self.color) * t,
+            }
+        }
+    }
+
+    // A vertex in pixel coordinates
+    #[derive(Copy, Clone, Debug)]
+    struct ScreenVertex {
+        x: f32,
+        y: f32,
+        depth: f32,
+        inverse_w: f32,
+        color: Vec4,
+    }
+
+    impl Rasterizer {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // The aspect ratio is taken from the framebuffer, not from the camera.
+        pub fn render<'a>(
+            &self,
+            framebuffer: &mut Framebuffer,
+            objects: impl IntoIterator<Item = &'a Object>,
+            camera: &Camera,
+        ) {
+            let aspect_ratio = framebuffer.width as f32 / framebuffer.height as f32;
+            let view_projection = camera.projection.matrix(aspect_ratio) * camera.view_matrix();
+
+            for object in objects {
+                for triangle in object.triangles.iter() {
+                    let clip_vertices = triangle.vertices.map(|vertex| {
+                        let position = vertex.position + object.position;
+
+                        ClipVertex {
+                            position: view_projection * Vec4::from([position.x_1, position.x_2, position.x_3, 1.0]),
+                            color: vertex.color,
+                        }
+                    });
+
+                    let polygon = clip_polygon(&clip_vertices);
+
+                    // The clipped polygon is convex, so it can be split into a fan
+                    for i in 1..polygon.len().saturating_sub(1) {
+                        self.draw_triangle(framebuffer, [polygon[0], polygon[i], polygon[i + 1]]);
+                    }
+                }
+            }
+        }
+
+        fn draw_triangle(&self, framebuffer: &mut Framebuffer, vertices: [ClipVertex; 3]) {
+            let width = framebuffer.width as f32;
+            let height = framebuffer.height as f32;
+
+            let [a, b, c] = vertices.map(|vertex| {
+                let inverse_w = 1.0 / vertex.position.x_4;
+
+                ScreenVertex {
+                    x: (vertex.position.x_1 * inverse_w + 1.0) * 0.5 * width,
+                    y: (1.0 - vertex.position.x_2 * inverse_w) * 0.5 * height,
+                    depth: vertex.position.x_3 * inverse_w,
+                    inverse_w,
+                    color: vertex.color * inverse_w,
+                }
+            });
+
+            // Pixel rows go down, so a counter-clockwise triangle has a negative area here
+            let area = edge(a, b, c.x, c.y);
+            if area == 0.0 {
+                return;
+            }
+
+            let is_front = match self.front_face {
+                wgpu::FrontFace::Ccw => area < 0.0,
+                wgpu::FrontFace::Cw => area > 0.0,
+            };
+
+            match (self.cull_mode, is_front) {
+                (Some(wgpu::Face::Back), false) | (Some(wgpu::Face::Front), true) => return,
+                _ => {}
+            }
+
+            let min_x = a.x.min(b.x).min(c.x).floor().max(0.0) as u32;
+            let min_y = a.y.min(b.y).min(c.y).floor().max(0.0) as u32;
+            let max_x = (a.x.max(b.x).max(c.x).ceil().min(width) as u32).min(framebuffer.width);
+            let max_y = (a.y.max(b.y).max(c.y).ceil().min(height) as u32).min(framebuffer.height);
+
+            for y in min_y..max_y {
+                for x in min_x..max_x {
+                    // Sample at the pixel center
+                    let sample_x = x as f32 + 0.5;
+                    let sample_y = y as f32 + 0.5;
+
+                    let weight_a = edge(b, c, sample_x, sample_y) / area;
+                    let weight_b = edge(c, a, sample_x, sample_y) / area;
+                    let weight_c = edge(a, b, sample_x, sample_y) / area;
+
+                    if weight_a < 0.0 || weight_b < 0.0 || weight_c < 0.0 {
+                        continue;
+                    }
+
+                    let depth = a.depth * weight_a + b.depth * weight_b + c.depth * weight_c;
+                    let index = (y * framebuffer.width + x) as usize;
+
+                    if !compare(self.depth_settings.compare, depth, framebuffer.depth[index]) {
+                        continue;
+                    }
+
+                    if self.depth_settings.write_enabled {
+                        framebuffer.depth[index] = depth;
+                    }
+
+                    // Perspective correct interpolation
+                    let inverse_w = a.inverse_w * weight_a + b.inverse_w * weight_b + c.inverse_w * weight_c;
+                    let color = (a.color * weight_a + b.color * weight_b + c.color * weight_c) / inverse_w;
+
+                    framebuffer.color[index] = color.to_array();
+                }
+            }
+        }
+    }
+
+    fn edge(a: ScreenVertex, b: ScreenVertex, x: f32, y: f32) -> f32 {
+        (b.x - a.x) * (y - a.y) - (b.y - a.y) * (x - a.x)
+    }
+
+    fn compare(function: wgpu::CompareFunction, value: f32, reference: f32) -> bool {
+        match function {
+            wgpu::CompareFunction::Never => false,
+            wgpu::CompareFunction::Less => value < reference,
+            wgpu::CompareFunction::Equal => value == reference,
+            wgpu::CompareFunction::LessEqual => value <= reference,
+            wgpu::CompareFunction::Greater => value > reference,
+            wgpu::CompareFunction::NotEqual => value != reference,
+            wgpu::CompareFunction::GreaterEqual => value >= reference,
+            wgpu::CompareFunction::Always => true,
+        }
+    }
+
+    // Clips a triangle against the near (z >= 0) and far (z <= w) planes of the
+    // wgpu clip space. The sides are left to the screen bounds of the rasterizer.
+    fn clip_polygon(vertices: &[ClipVertex; 3]) -> Vec<ClipVertex> {
+        let near = |vertex: &ClipVertex| vertex.position.x_3;
+        let far = |vertex: &ClipVertex| vertex.position.x_4 - vertex.position.x_3;
+
+        let polygon = clip_against(vertices.to_vec(), near);
+        clip_against(polygon, far)
+    }
+
+    fn clip_against(polygon: Vec<ClipVertex>, distance: impl Fn(&ClipVertex) -> f32) -> Vec<ClipVertex> {
+        let mut result = Vec::with_capacity(polygon.len() + 1);
+
+        for i in 0..polygon.len() {
+            let current = polygon[i];
+            let next = polygon[(i + 1) % polygon.len()];
+            let current_distance = distance(&current);
+            let next_distance = distance(&next);
+
+            if current_distance >= 0.0 {
+                result.push(current);
+            }
+
+            if (current_distance >= 0.0) != (next_distance >= 0.0) {
+                let t = current_distance / (current_distance - next_distance);
+                result.push(current.lerp(next, t));
+            }
+        }
+
+        result
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::camera::camera::Projection;
+        use crate::object::object::{Triangle, Vertex};
+
+        const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+        const GREEN: [f32; 4] = [0.0, 1.0, 0.0, 1.0];
+        const CLEAR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+        // Looks down the z axis from the origin
+        fn camera() -> Camera {
+            Camera::new([0.0, 0.0, 0.0], 0.0, 0.0, 0.005, Projection::default())
+        }
+
+        // Counter-clockwise as seen from the camera
+        fn triangle(corners: [[f32; 3]; 3], color: [f32; 4]) -> Object {
+            Object::new([0.0, 0.0, 0.0], vec![Triangle::new(corners.map(|corner| Vertex::new(corner, color)))], false)
+        }
+
+        fn facing(z: f32, color: [f32; 4]) -> Object {
+            triangle([[-1.0, -1.0, z], [1.0, -1.0, z], [0.0, 1.0, z]], color)
+        }
+
+        fn center(rasterizer: Rasterizer, objects: &[Object]) -> [f32; 4] {
+            let mut framebuffer = Framebuffer::new(16, 16);
+            rasterizer.render(&mut framebuffer, objects, &camera());
+            framebuffer.pixel(8, 8)
+        }
+
+        #[test]
+        fn back_faces_are_culled() {
+            let front = facing(2.0, RED);
+            let mut back = front.clone();
+            back.triangles[0].vertices.swap(1, 2);
+
+            let ccw = Rasterizer::new();
+            assert_eq!(center(ccw, std::slice::from_ref(&front)), RED);
+            assert_eq!(center(ccw, std::slice::from_ref(&back)), CLEAR);
+
+            let cw = Rasterizer { front_face: wgpu::FrontFace::Cw, ..Rasterizer::new() };
+            assert_eq!(center(cw, std::slice::from_ref(&front)), CLEAR);
+            assert_eq!(center(cw, std::slice::from_ref(&back)), RED);
+
+            let unculled = Rasterizer { cull_mode: None, ..Rasterizer::new() };
+            assert_eq!(center(unculled, std::slice::from_ref(&back)), RED);
+        }
+
+        #[test]
+        fn depth_compare_decides_overlaps() {
+            let near = facing(2.0, RED);
+            let far = facing(4.0, GREEN);
+
+            let less = Rasterizer::new();
+            assert_eq!(center(less, &[near.clone(), far.clone()]), RED);
+            assert_eq!(center(less, &[far.clone(), near.clone()]), RED);
+
+            let always = Rasterizer {
+                depth_settings: DepthSettings { compare: wgpu::CompareFunction::Always, write_enabled: true },
+                ..Rasterizer::new()
+            };
+            assert_eq!(center(always, &[near.clone(), far.clone()]), GREEN);
+
+            // Without depth writes every fragment passes against the cleared depth
+            let unwritten = Rasterizer {
+                depth_settings: DepthSettings { compare: wgpu::CompareFunction::Less, write_enabled: false },
+                ..Rasterizer::new()
+            };
+            assert_eq!(center(unwritten, &[near, far]), GREEN);
+        }
+
+        #[test]
+        fn triangles_are_clipped_at_the_near_plane() {
+            // Reaches behind the camera, only the part in front is drawn
+            let crossing = triangle([[-1.0, -1.0, 2.0], [1.0, -1.0, 2.0], [0.0, 3.0, -2.0]], RED);
+            let mut framebuffer = Framebuffer::new(16, 16);
+            Rasterizer { cull_mode: None, ..Rasterizer::new() }.render(&mut framebuffer, [&crossing], &camera());
+
+            assert_eq!(framebuffer.pixel(8, 12), RED);
+            assert!(framebuffer.depth.iter().all(|&depth| (0.0..=1.0).contains(&depth)));
+
+            let behind = facing(-2.0, RED);
+            let unculled = Rasterizer { cull_mode: None, ..Rasterizer::new() };
+            assert_eq!(center(unculled, &[behind]), CLEAR);
+        }
+    }
+}
@@ -0,0 +1,110 @@
+// Tree structure of RaycastHit:
+//
+// * mesh: MeshId
+// * instance: Option<InstanceId>
+// * object: &Object
+// * triangle: usize
+// * point: [f32; 3]
+// * normal: [f32; 3]
+// * distance: f32
+//
+// Rays are tested against every mesh, not only the ones flagged with
+// `collision`. Each mesh moves the ray into object space with its inverse model
+// matrix instead of transforming its triangles, and is skipped when the ray
+// misses its bounding box. Distances stay in world units since the direction
+// is transformed without normalizing it. Meshes with instances are tested once
+// per instance, with the world matrix of that instance.
+
+pub mod raycast {
+    use crate::mesh::mesh::{InstanceId, Mesh, MeshId, MeshRegistry};
+    use crate::object::object::Object;
+    use crate::object::object::gmlib::matrix::*;
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct RaycastHit<'a> {
+        pub mesh: MeshId,
+        // None for meshes drawn without instances
+        pub instance: Option<InstanceId>,
+        pub object: &'a Object,
+        // Index into object.triangles
+        pub triangle: usize,
+        pub point: Vec3,
+        // World space, unit length, facing the ray origin
+        pub normal: Vec3,
+        pub distance: f32,
+    }
+
+    // Closest hit no further than max_distance along the ray
+    pub fn raycast(meshes: &MeshRegistry, ray: Ray, max_distance: f32) -> Option<RaycastHit<'_>> {
+        raycast_filtered(meshes, ray, max_distance, |_, _| true)
+    }
+
+    // Like raycast, ignoring meshes the filter returns false for
+    pub fn raycast_filtered<'a>(
+        meshes: &'a MeshRegistry,
+        ray: Ray,
+        max_distance: f32,
+        filter: impl Fn(MeshId, &Object) -> bool,
+    ) -> Option<RaycastHit<'a>> {
+        // Zero directions turn into NaN when normalized
+        let length = ray.direction.magnitude();
+        if length.is_nan() || length == 0.0 {
+            return None;
+        }
+
+        let mut closest: Option<RaycastHit> = None;
+
+        for (id, mesh) in meshes.iter().filter(|(id, mesh)| filter(*id, &mesh.object)) {
+            for (instance, model) in mesh.world_matrices() {
+                let max_distance = closest.map_or(max_distance, |hit| hit.distance);
+
+                if let Some((triangle, distance)) = raycast_mesh(mesh, model, ray, max_distance) {
+                    let normal = model.normal_matrix() * object_normal(&mesh.object, triangle);
+                    let normal = normal.normalize();
+
+                    closest = Some(RaycastHit {
+                        mesh: id,
+                        instance,
+                        object: &mesh.object,
+                        triangle,
+                        point: ray.at(distance),
+                        normal: if normal * ray.direction > 0.0 { -normal } else { normal },
+                        distance,
+                    });
+                }
+            }
+        }
+
+        closest
+    }
+
+    // Closest triangle of the mesh placed by model hit within max_distance, and the distance to it
+    fn raycast_mesh(mesh: &Mesh, model: Mat4x4, ray: Ray, max_distance: f32) -> Option<(usize, f32)> {
+        let bounds = mesh.bounds()?;
+
+        let inverse = model.inverse();
+        let origin = inverse * Vec4::from([ray.origin.x_1, ray.origin.x_2, ray.origin.x_3, 1.0]);
+        let direction = inverse * Vec4::from([ray.direction.x_1, ray.direction.x_2, ray.direction.x_3, 0.0]);
+        let local = Ray {
+            origin: Vec3::from([origin.x_1, origin.x_2, origin.x_3]),
+            direction: Vec3::from([direction.x_1, direction.x_2, direction.x_3]),
+        };
+
+        if local.intersect_aabb(bounds.min, bounds.max).is_none_or(|distance| distance > max_distance) {
+            return None;
+        }
+
+        mesh.object.triangles.iter()
+            .enumerate()
+            .filter_map(|(index, triangle)| {
+                let distance = local.intersect_triangle(triangle.vertices.map(|vertex| vertex.position))?;
+                (distance <= max_distance).then_some((index, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    fn object_normal(object: &Object, triangle: usize) -> Vec3 {
+        let [a, b, c] = object.triangles[triangle].vertices.map(|vertex| vertex.position);
+        (b - a) % (c - a)
+    }
+}
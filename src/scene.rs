@@ -0,0 +1,294 @@
+// Tree structure of SceneGraph:
+//
+// * nodes: BTreeMap<NodeId, Node>
+//   - name: Option<String>
+//   - transform: Transform
+//     * translation: [f32; 3]
+//     * rotation: Quaternion
+//     * scale: [f32; 3]
+//   - parent: Option<NodeId>
+//   - children: Vec<NodeId>
+//   - meshes: Vec<MeshId>
+//   - world: Mat4x4
+//   - dirty: bool
+// * next_id: u32
+//
+// World matrices are only recomputed for nodes whose transform (or whose
+// ancestor's transform) changed since the last update_world_matrices().
+
+pub mod scene {
+    use std::collections::BTreeMap;
+
+    use crate::mesh::mesh::MeshId;
+    use crate::object::object::gmlib::matrix::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct NodeId(u32);
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct Transform {
+        pub translation: Vec3,
+        pub rotation: Quaternion,
+        pub scale: Vec3,
+    }
+
+    impl Default for Transform {
+        fn default() -> Self {
+            Self {
+                translation: Vec3::from([0.0, 0.0, 0.0]),
+                rotation: UNIT_QUATERNION,
+                scale: Vec3::from([1.0, 1.0, 1.0]),
+            }
+        }
+    }
+
+    impl Transform {
+        pub fn from_translation(translation: [f32; 3]) -> Self {
+            Self {
+                translation: Vec3::from(translation),
+                ..Self::default()
+            }
+        }
+
+        // Scales first, then rotates, then translates
+        pub fn matrix(&self) -> Mat4x4 {
+            let rotation = self.rotation.normalize();
+            let x = rotation.rotate_vector(Vec3::from([1.0, 0.0, 0.0])) * self.scale.x_1;
+            let y = rotation.rotate_vector(Vec3::from([0.0, 1.0, 0.0])) * self.scale.x_2;
+            let z = rotation.rotate_vector(Vec3::from([0.0, 0.0, 1.0])) * self.scale.x_3;
+            let t = self.translation;
+
+            Mat4x4::from([
+                x.x_1, y.x_1, z.x_1, t.x_1,
+                x.x_2, y.x_2, z.x_2, t.x_2,
+                x.x_3, y.x_3, z.x_3, t.x_3,
+                0.0,   0.0,   0.0,   1.0,
+            ])
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct Node {
+        pub name: Option<String>,
+        transform: Transform,
+        parent: Option<NodeId>,
+        children: Vec<NodeId>,
+        meshes: Vec<MeshId>,
+        world: Mat4x4,
+        dirty: bool,
+    }
+
+    impl Node {
+        pub fn transform(&self) -> &Transform {
+            &self.transform
+        }
+
+        pub fn parent(&self) -> Option<NodeId> {
+            self.parent
+        }
+
+        pub fn children(&self) -> &[NodeId] {
+            &self.children
+        }
+
+        pub fn meshes(&self) -> &[MeshId] {
+            &self.meshes
+        }
+
+        // Only up to date after SceneGraph::update_world_matrices()
+        pub fn world_matrix(&self) -> Mat4x4 {
+            self.world
+        }
+    }
+
+    #[derive(Default)]
+    pub struct SceneGraph {
+        nodes: BTreeMap<NodeId, Node>,
+        next_id: u32,
+    }
+
+    impl SceneGraph {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        // Adds a node below parent, or a root node without a parent.
+        // Returns None when the parent does not exist.
+        pub fn add(&mut self, parent: Option<NodeId>, transform: Transform) -> Option<NodeId> {
+            if parent.is_some_and(|parent| !self.nodes.contains_key(&parent)) {
+                return None;
+            }
+
+            let id = NodeId(self.next_id);
+            self.next_id += 1;
+
+            self.nodes.insert(id, Node {
+                name: None,
+                transform,
+                parent,
+                children: Vec::new(),
+                meshes: Vec::new(),
+                world: UNIT_MAT4X4,
+                dirty: true,
+            });
+
+            if let Some(parent) = parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+                parent.children.push(id);
+            }
+
+            Some(id)
+        }
+
+        // Removes the node and all of its descendants, returns the meshes that were attached to them.
+        pub fn remove(&mut self, id: NodeId) -> Vec<MeshId> {
+            let node = match self.nodes.remove(&id) {
+                Some(node) => node,
+                None => return Vec::new(),
+            };
+
+            if let Some(parent) = node.parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+                parent.children.retain(|&child| child != id);
+            }
+
+            let mut meshes = node.meshes;
+            let mut stack = node.children;
+
+            while let Some(child) = stack.pop() {
+                if let Some(child) = self.nodes.remove(&child) {
+                    meshes.extend(child.meshes);
+                    stack.extend(child.children);
+                }
+            }
+
+            meshes
+        }
+
+        pub fn get(&self, id: NodeId) -> Option<&Node> {
+            self.nodes.get(&id)
+        }
+
+        pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+            self.nodes.iter().map(|(id, node)| (*id, node))
+        }
+
+        pub fn roots(&self) -> impl Iterator<Item = NodeId> + '_ {
+            self.nodes.iter()
+                .filter(|(_, node)| node.parent.is_none())
+                .map(|(id, _)| *id)
+        }
+
+        pub fn len(&self) -> usize {
+            self.nodes.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.nodes.is_empty()
+        }
+
+        pub fn set_name(&mut self, id: NodeId, name: impl Into<String>) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.name = Some(name.into());
+            }
+        }
+
+        pub fn set_transform(&mut self, id: NodeId, transform: Transform) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.transform = transform;
+                node.dirty = true;
+            }
+        }
+
+        pub fn transform_mut(&mut self, id: NodeId) -> Option<&mut Transform> {
+            self.nodes.get_mut(&id).map(|node| {
+                node.dirty = true;
+                &mut node.transform
+            })
+        }
+
+        // Moves the node below a new parent, or makes it a root node. The local
+        // transform is kept, so the node moves along with its new parent.
+        // Returns false if either node does not exist or it would create a cycle.
+        pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool {
+            if !self.nodes.contains_key(&id) {
+                return false;
+            }
+
+            if let Some(parent) = parent {
+                let mut ancestor = Some(parent);
+                while let Some(current) = ancestor {
+                    if current == id {
+                        return false;
+                    }
+
+                    ancestor = match self.nodes.get(&current) {
+                        Some(node) => node.parent,
+                        None => return false,
+                    };
+                }
+            }
+
+            let old_parent = self.nodes[&id].parent;
+            if let Some(old_parent) = old_parent.and_then(|old_parent| self.nodes.get_mut(&old_parent)) {
+                old_parent.children.retain(|&child| child != id);
+            }
+
+            if let Some(new_parent) = parent.and_then(|parent| self.nodes.get_mut(&parent)) {
+                new_parent.children.push(id);
+            }
+
+            let node = self.nodes.get_mut(&id).unwrap();
+            node.parent = parent;
+            node.dirty = true;
+
+            true
+        }
+
+        pub fn attach_mesh(&mut self, id: NodeId, mesh: MeshId) -> bool {
+            match self.nodes.get_mut(&id) {
+                Some(node) => {
+                    node.meshes.push(mesh);
+                    node.dirty = true;
+                    true
+                }
+                None => false,
+            }
+        }
+
+        pub fn detach_mesh(&mut self, id: NodeId, mesh: MeshId) {
+            if let Some(node) = self.nodes.get_mut(&id) {
+                node.meshes.retain(|&attached| attached != mesh);
+            }
+        }
+
+        // Recomputes the world matrices of changed nodes and their descendants,
+        // calls changed() for every mesh attached to a recomputed node.
+        pub fn update_world_matrices(&mut self, mut changed: impl FnMut(MeshId, Mat4x4)) {
+            let roots: Vec<NodeId> = self.roots().collect();
+            let mut stack: Vec<(NodeId, Mat4x4, bool)> = roots.into_iter()
+                .map(|root| (root, UNIT_MAT4X4, false))
+                .collect();
+
+            while let Some((id, parent_world, parent_changed)) = stack.pop() {
+                let node = match self.nodes.get_mut(&id) {
+                    Some(node) => node,
+                    None => continue,
+                };
+
+                let is_changed = parent_changed || node.dirty;
+
+                if is_changed {
+                    node.world = parent_world * node.transform.matrix();
+                    node.dirty = false;
+
+                    for &mesh in node.meshes.iter() {
+                        changed(mesh, node.world);
+                    }
+                }
+
+                for &child in node.children.iter() {
+                    stack.push((child, node.world, is_changed));
+                }
+            }
+        }
+    }
+}
@@ -1,64 +1,201 @@
 struct CameraUniform {
     position: vec3<f32>,
-    matrix: mat3x3<f32>,
-    /*
-    matrix_row_1: vec3<f32>,
-    matrix_row_2: vec3<f32>,
-    matrix_row_3: vec3<f32>,
-    */
-    //scale_factor: f32,
+    view_projection: mat4x4<f32>,
+}
+
+const MAX_LIGHTS: u32 = 32u;
+
+const LIGHT_DIRECTIONAL: u32 = 0u;
+const LIGHT_POINT: u32 = 1u;
+const LIGHT_SPOT: u32 = 2u;
+
+struct Light {
+    position: vec3<f32>,
+    kind: u32,
+    direction: vec3<f32>,
+    intensity: f32,
+    color: vec3<f32>,
+    inner_cone_cos: f32,
+    // Constant, linear and quadratic falloff
+    attenuation: vec3<f32>,
+    outer_cone_cos: f32,
+}
+
+struct LightsUniform {
+    ambient: vec3<f32>,
+    count: u32,
+    lights: array<Light, MAX_LIGHTS>,
 }
 
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 
+@group(1) @binding(0)
+var<uniform> lights: LightsUniform;
+
+struct ModelUniform {
+    model: mat4x4<f32>,
+    // Inverse transpose of the model matrix, for normals
+    normal: mat3x3<f32>,
+    // Mixed over the shaded color by its alpha
+    highlight: vec4<f32>,
+    // Written by fs_pick, 0 is left for the background
+    pick_id: u32,
+}
+
+@group(2) @binding(0)
+var<uniform> model_uniform: ModelUniform;
+
+const ALPHA_OPAQUE: u32 = 0u;
+const ALPHA_MASK: u32 = 1u;
+const ALPHA_BLEND: u32 = 2u;
+
+struct MaterialUniform {
+    base_color: vec4<f32>,
+    emissive: vec3<f32>,
+    roughness: f32,
+    specular: f32,
+    alpha_mode: u32,
+    alpha_cutoff: f32,
+}
+
+@group(3) @binding(0)
+var<uniform> material: MaterialUniform;
+// White for materials without a texture
+@group(3) @binding(1)
+var base_color_texture: texture_2d<f32>;
+@group(3) @binding(2)
+var base_color_sampler: sampler;
+
 struct VertexInput {
     @location(0) position: vec3<f32>,
-    @location(1) color: vec3<f32>,
-    @location(2) camera_position: vec3<f32>,
-    @location(3) camera_matrix_row_1: vec3<f32>,
-    @location(4) camera_matrix_row_2: vec3<f32>,
-    @location(5) camera_matrix_row_3: vec3<f32>,
-	@location(6) depth_factor: f32,
-    @location(7) normal: vec3<f32>,
-    @location(8) light_source: vec3<f32>,
+    @location(1) color: vec4<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) uv: vec2<f32>,
+};
+
+// Columns of the instance matrices
+struct InstanceInput {
+    @location(4) model_0: vec4<f32>,
+    @location(5) model_1: vec4<f32>,
+    @location(6) model_2: vec4<f32>,
+    @location(7) model_3: vec4<f32>,
+    @location(8) normal_0: vec3<f32>,
+    @location(9) normal_1: vec3<f32>,
+    @location(10) normal_2: vec3<f32>,
+    @location(11) tint: vec4<f32>,
 };
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
-    @location(0) color: vec3<f32>,
+    @location(0) color: vec4<f32>,
+    @location(1) world_position: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+    @location(3) uv: vec2<f32>,
 };
 
 @vertex
-fn vs_main(model: VertexInput) -> VertexOutput {
+fn vs_main(model: VertexInput, instance: InstanceInput) -> VertexOutput {
     var out: VertexOutput;
 
-    /*
-	var camera_matrix = mat3x3<f32>(
-		camera.matrix_row_1,
-		camera.matrix_row_2,
-		camera.matrix_row_3,
-	);
-    */
+    let instance_model = mat4x4<f32>(instance.model_0, instance.model_1, instance.model_2, instance.model_3);
+    let instance_normal = mat3x3<f32>(instance.normal_0, instance.normal_1, instance.normal_2);
 
-    var result = camera.matrix * (model.position - camera.position);
+    let world_position = model_uniform.model * instance_model * vec4<f32>(model.position, 1.0);
 
-	out.clip_position = vec4<f32>(result, result.z * model.depth_factor);
+    out.clip_position = camera.view_projection * world_position;
+    out.color = model.color * instance.tint;
+    out.world_position = world_position.xyz;
+    out.normal = model_uniform.normal * instance_normal * model.normal;
+    out.uv = model.uv;
 
-    // With lighting
-    /*
-    var lighting_factor = dot(model.normal, normalize(model.position - model.light_source));
-    if lighting_factor < 0.2 { lighting_factor = 0.2; }
-    out.color = model.color * lighting_factor;
-    */
+	return out;
+}
 
-    // Without lighting
-    out.color = model.color;
+// Vertex color, material color and texture, masked fragments are discarded
+fn base_color(in: VertexOutput) -> vec4<f32> {
+    var color = in.color * material.base_color * textureSample(base_color_texture, base_color_sampler, in.uv);
 
-	return out;
+    if material.alpha_mode == ALPHA_MASK && color.a < material.alpha_cutoff {
+        discard;
+    }
+    if material.alpha_mode != ALPHA_BLEND {
+        color.a = 1.0;
+    }
+
+    return color;
+}
+
+@fragment
+fn fs_main(in: VertexOutput, @builtin(front_facing) front_facing: bool) -> @location(0) vec4<f32> {
+    let base_color = base_color(in);
+
+    // Without lights, or without a normal, the surface is drawn unlit
+    if lights.count == 0u || dot(in.normal, in.normal) == 0.0 {
+        return apply_highlight(vec4<f32>(base_color.rgb + material.emissive, base_color.a));
+    }
+
+    // Back faces of double-sided materials are lit from their own side
+    let normal = select(-1.0, 1.0, front_facing) * normalize(in.normal);
+    let view_direction = normalize(camera.position - in.world_position);
+
+    var diffuse = vec3<f32>(0.0);
+    var specular = vec3<f32>(0.0);
+
+    // Blinn-Phong exponent matching the roughness, 0.5 gives 30. Kept at 1 or above,
+    // an exponent of 0 would light the back of the highlight lobe as much as its peak.
+    let alpha = material.roughness * material.roughness;
+    let shininess = max(2.0 / max(alpha * alpha, 1e-4) - 2.0, 1.0);
+
+    for (var i = 0u; i < min(lights.count, MAX_LIGHTS); i += 1u) {
+        let light = lights.lights[i];
+
+        var light_direction: vec3<f32>;
+        var strength = light.intensity;
+
+        if light.kind == LIGHT_DIRECTIONAL {
+            light_direction = normalize(-light.direction);
+        } else {
+            let offset = light.position - in.world_position;
+            let distance = length(offset);
+            // A fragment right at the light gets no direction and no light instead of NaN
+            light_direction = offset / max(distance, 1e-6);
+
+            strength /= light.attenuation.x
+                + light.attenuation.y * distance
+                + light.attenuation.z * distance * distance;
+
+            if light.kind == LIGHT_SPOT {
+                let cone_cos = dot(-light_direction, normalize(light.direction));
+                strength *= smoothstep(light.outer_cone_cos, light.inner_cone_cos, cone_cos);
+            }
+        }
+
+        let lambert = max(dot(normal, light_direction), 0.0);
+        if lambert <= 0.0 {
+            continue;
+        }
+
+        // Blinn-Phong uses the half vector instead of the reflected light direction
+        let half_direction = normalize(light_direction + view_direction);
+        let highlight = pow(max(dot(normal, half_direction), 0.0), shininess);
+
+        diffuse += light.color * strength * lambert;
+        specular += light.color * strength * highlight * material.specular;
+    }
+
+    let color = base_color.rgb * (lights.ambient + diffuse) + specular + material.emissive;
+
+    return apply_highlight(vec4<f32>(color, base_color.a));
+}
+
+fn apply_highlight(color: vec4<f32>) -> vec4<f32> {
+    let highlight = model_uniform.highlight;
+    return vec4<f32>(mix(color.rgb, highlight.rgb, highlight.a), color.a);
 }
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+fn fs_pick(in: VertexOutput) -> @location(0) u32 {
+    _ = base_color(in);
+    return model_uniform.pick_id;
 }
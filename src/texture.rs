@@ -0,0 +1,367 @@
+// Tree structure of TextureRegistry:
+//
+// * textures: BTreeMap<TextureId, TextureEntry>
+//   - texture: Texture
+//     * texture: wgpu::Texture
+//     * view: wgpu::TextureView
+//     * sampler: wgpu::Sampler
+//   - sampler: SamplerSettings
+// * white: Texture
+// * next_id: u32
+// * generation: u64
+//
+// Images are uploaded as sRGB with a full mip chain, every level is half the
+// size of the previous one and filtered on the CPU. The filtering happens on
+// linear colors, averaging sRGB values directly would darken the smaller
+// levels. Images larger than the device supports are refused. Textures are
+// bound through the materials referencing them. Materials without a texture,
+// or with one that has been removed, use a 1x1 white texture instead.
+
+pub mod texture {
+    use std::collections::BTreeMap;
+    use std::path::Path;
+    use anyhow::Context;
+
+    pub struct Texture {
+        pub texture: wgpu::Texture,
+        pub view: wgpu::TextureView,
+        pub sampler: wgpu::Sampler,
+    }
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub struct SamplerSettings {
+        pub mag_filter: wgpu::FilterMode,
+        pub min_filter: wgpu::FilterMode,
+        // Filtering between mip levels
+        pub mipmap_filter: wgpu::FilterMode,
+        pub address_mode_u: wgpu::AddressMode,
+        pub address_mode_v: wgpu::AddressMode,
+        // 1 turns anisotropic filtering off, it needs all filters to be linear
+        pub anisotropy: u16,
+    }
+
+    impl Default for SamplerSettings {
+        fn default() -> Self {
+            Self {
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Linear,
+                address_mode_u: wgpu::AddressMode::Repeat,
+                address_mode_v: wgpu::AddressMode::Repeat,
+                anisotropy: 1,
+            }
+        }
+    }
+
+    impl SamplerSettings {
+        // Sharp texels, for pixel art
+        pub fn nearest() -> Self {
+            Self {
+                mag_filter: wgpu::FilterMode::Nearest,
+                min_filter: wgpu::FilterMode::Nearest,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                ..Self::default()
+            }
+        }
+
+        pub fn create_sampler(&self, device: &wgpu::Device) -> wgpu::Sampler {
+            let linear = self.mag_filter == wgpu::FilterMode::Linear
+                && self.min_filter == wgpu::FilterMode::Linear
+                && self.mipmap_filter == wgpu::FilterMode::Linear;
+
+            device.create_sampler(&wgpu::SamplerDescriptor {
+                label: Some("Texture sampler"),
+                address_mode_u: self.address_mode_u,
+                address_mode_v: self.address_mode_v,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: self.mag_filter,
+                min_filter: self.min_filter,
+                mipmap_filter: self.mipmap_filter,
+                anisotropy_clamp: if linear { self.anisotropy.clamp(1, 16) } else { 1 },
+                ..Default::default()
+            })
+        }
+    }
+
+    impl Texture {
+        pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
+        pub const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+        // The depth texture has to match the size of the surface, so it is
+        // recreated every time the surface is resized.
+        pub fn create_depth_texture(
+            device: &wgpu::Device,
+            config: &wgpu::SurfaceConfiguration,
+            label: &str,
+        ) -> Self {
+            let size = wgpu::Extent3d {
+                width: config.width.max(1),
+                height: config.height.max(1),
+                depth_or_array_layers: 1,
+            };
+
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::DEPTH_FORMAT,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+                address_mode_u: wgpu::AddressMode::ClampToEdge,
+                address_mode_v: wgpu::AddressMode::ClampToEdge,
+                address_mode_w: wgpu::AddressMode::ClampToEdge,
+                mag_filter: wgpu::FilterMode::Linear,
+                min_filter: wgpu::FilterMode::Linear,
+                mipmap_filter: wgpu::FilterMode::Nearest,
+                compare: Some(wgpu::CompareFunction::LessEqual),
+                lod_min_clamp: 0.0,
+                lod_max_clamp: 100.0,
+                ..Default::default()
+            });
+
+            Self {
+                texture,
+                view,
+                sampler,
+            }
+        }
+
+        // Reads a PNG, BMP or TGA file, the format is taken from the extension.
+        pub fn load(
+            device: &wgpu::Device,
+            queue: &wgpu::Queue,
+            path: impl AsRef<Path>,
+            sampler: SamplerSettings,
+        ) -> anyhow::Result<Self> {
+            let path = path.as_ref();
+            let image = image::open(path)
+                .with_context(|| format!("Unable to load {}", path.display()))?;
+            let label = path.display().to_string();
+
+            Self::from_image(device, queue, &image, &label, sampler)
+        }
+
+        // PNG and BMP are recognized by their signature, anything else is read as TGA
+        // since TGA files have none.
+        pub fn from_bytes(
+            device: &wgpu::Device,
+            queue: &wgpu::Queue,
+            bytes: &[u8],
+            label: &str,
+            sampler: SamplerSettings,
+        ) -> anyhow::Result<Self> {
+            let format = image::guess_format(bytes).unwrap_or(image::ImageFormat::Tga);
+            let image = image::load_from_memory_with_format(bytes, format)
+                .with_context(|| format!("Unable to decode {}", label))?;
+
+            Self::from_image(device, queue, &image, label, sampler)
+        }
+
+        pub fn from_image(
+            device: &wgpu::Device,
+            queue: &wgpu::Queue,
+            image: &image::DynamicImage,
+            label: &str,
+            sampler: SamplerSettings,
+        ) -> anyhow::Result<Self> {
+            let image = image.to_rgba8();
+            let (width, height) = image.dimensions();
+
+            let max_size = device.limits().max_texture_dimension_2d;
+            if width > max_size || height > max_size {
+                anyhow::bail!("{} is {}x{}, larger than the {} pixels the device supports", label, width, height, max_size);
+            }
+
+            let mip_level_count = width.max(height).max(1).ilog2() + 1;
+
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: Self::FORMAT,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            });
+
+            let mut linear = image::Rgba32FImage::from_fn(width, height, |x, y| {
+                let [r, g, b, a] = image.get_pixel(x, y).0.map(|value| value as f32 / 255.0);
+                image::Rgba([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a])
+            });
+
+            let mut level = image;
+            for mip_level in 0..mip_level_count {
+                if mip_level > 0 {
+                    let (width, height) = linear.dimensions();
+                    linear = image::imageops::resize(
+                        &linear,
+                        (width / 2).max(1),
+                        (height / 2).max(1),
+                        image::imageops::FilterType::Triangle,
+                    );
+
+                    level = image::RgbaImage::from_fn(linear.width(), linear.height(), |x, y| {
+                        let [r, g, b, a] = linear.get_pixel(x, y).0;
+                        image::Rgba([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a]
+                            .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8))
+                    });
+                }
+
+                let (width, height) = level.dimensions();
+                queue.write_texture(
+                    wgpu::TexelCopyTextureInfo {
+                        texture: &texture,
+                        mip_level,
+                        origin: wgpu::Origin3d::ZERO,
+                        aspect: wgpu::TextureAspect::All,
+                    },
+                    level.as_raw(),
+                    wgpu::TexelCopyBufferLayout {
+                        offset: 0,
+                        bytes_per_row: Some(width * 4),
+                        rows_per_image: Some(height),
+                    },
+                    wgpu::Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+            Ok(Self {
+                texture,
+                view,
+                sampler: sampler.create_sampler(device),
+            })
+        }
+    }
+
+    fn srgb_to_linear(value: f32) -> f32 {
+        if value <= 0.04045 {
+            value / 12.92
+        } else {
+            ((value + 0.055) / 1.055).powf(2.4)
+        }
+    }
+
+    fn linear_to_srgb(value: f32) -> f32 {
+        if value <= 0.0031308 {
+            value * 12.92
+        } else {
+            1.055 * value.powf(1.0 / 2.4) - 0.055
+        }
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct TextureId(u32);
+
+    struct TextureEntry {
+        texture: Texture,
+        sampler: SamplerSettings,
+    }
+
+    pub struct TextureRegistry {
+        textures: BTreeMap<TextureId, TextureEntry>,
+        white: Texture,
+        next_id: u32,
+        // Counts changes to the views and samplers, bind groups holding them are
+        // rebuilt when it moves on
+        generation: u64,
+    }
+
+    impl TextureRegistry {
+        pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+            let white = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(1, 1, image::Rgba([255; 4])));
+
+            Self {
+                textures: BTreeMap::new(),
+                white: Texture::from_image(device, queue, &white, "White texture", SamplerSettings::default())
+                    .expect("A 1x1 texture fits on every device"),
+                next_id: 0,
+                generation: 0,
+            }
+        }
+
+        pub fn add(&mut self, texture: Texture, sampler: SamplerSettings) -> TextureId {
+            let id = TextureId(self.next_id);
+            self.next_id += 1;
+
+            self.textures.insert(id, TextureEntry { texture, sampler });
+            self.generation += 1;
+
+            id
+        }
+
+        pub fn load(
+            &mut self,
+            device: &wgpu::Device,
+            queue: &wgpu::Queue,
+            path: impl AsRef<Path>,
+            sampler: SamplerSettings,
+        ) -> anyhow::Result<TextureId> {
+            let texture = Texture::load(device, queue, path, sampler)?;
+
+            Ok(self.add(texture, sampler))
+        }
+
+        pub fn remove(&mut self, id: TextureId) -> Option<Texture> {
+            let entry = self.textures.remove(&id)?;
+            self.generation += 1;
+
+            Some(entry.texture)
+        }
+
+        pub fn get(&self, id: TextureId) -> Option<&Texture> {
+            self.textures.get(&id).map(|entry| &entry.texture)
+        }
+
+        // The white texture for None and for textures that do not exist
+        pub fn get_or_white(&self, id: Option<TextureId>) -> &Texture {
+            id.and_then(|id| self.get(id)).unwrap_or(&self.white)
+        }
+
+        pub fn sampler(&self, id: TextureId) -> Option<SamplerSettings> {
+            self.textures.get(&id).map(|entry| entry.sampler)
+        }
+
+        // False if the texture does not exist
+        pub fn set_sampler(&mut self, device: &wgpu::Device, id: TextureId, sampler: SamplerSettings) -> bool {
+            let Some(entry) = self.textures.get_mut(&id) else {
+                return false;
+            };
+
+            entry.texture.sampler = sampler.create_sampler(device);
+            entry.sampler = sampler;
+            self.generation += 1;
+
+            true
+        }
+
+        pub fn generation(&self) -> u64 {
+            self.generation
+        }
+
+        pub fn len(&self) -> usize {
+            self.textures.len()
+        }
+
+        pub fn is_empty(&self) -> bool {
+            self.textures.is_empty()
+        }
+    }
+}
@@ -0,0 +1,104 @@
+// Fixed-timestep game loop.
+//
+// Frame times are collected in an accumulator which is drained in steps of
+// exactly 1 / tick_rate seconds, so the simulation advances the same way
+// regardless of the frame rate. What is left in the accumulator is exposed as
+// alpha, the fraction of a tick the rendered frame lies past the last tick.
+
+pub mod timestep {
+    #[derive(Copy, Clone, Debug)]
+    pub struct FixedTimestep {
+        tick_rate: f32,
+        // Against the spiral of death: when the simulation cannot keep up,
+        // the missing time is dropped instead of piling up
+        max_steps: u32,
+        accumulator: f32,
+        ticks: u64,
+        // Time dropped since the last warning, which is logged at most once a second
+        skipped: f32,
+        next_warning: u64,
+    }
+
+    impl Default for FixedTimestep {
+        fn default() -> Self {
+            Self::new(60.0)
+        }
+    }
+
+    impl FixedTimestep {
+        pub fn new(tick_rate: f32) -> Self {
+            Self {
+                tick_rate: tick_rate.max(1.0),
+                max_steps: 8,
+                accumulator: 0.0,
+                ticks: 0,
+                skipped: 0.0,
+                next_warning: 0,
+            }
+        }
+
+        pub fn tick_rate(&self) -> f32 {
+            self.tick_rate
+        }
+
+        pub fn set_tick_rate(&mut self, tick_rate: f32) {
+            // Keep alpha where it is
+            let alpha = self.alpha();
+            self.tick_rate = tick_rate.max(1.0);
+            self.accumulator = alpha * self.delta_time();
+        }
+
+        pub fn max_steps(&self) -> u32 {
+            self.max_steps
+        }
+
+        // At least one tick is simulated per frame, otherwise time would stand still
+        pub fn set_max_steps(&mut self, max_steps: u32) {
+            self.max_steps = max_steps.max(1);
+        }
+
+        // Length of one tick in seconds
+        pub fn delta_time(&self) -> f32 {
+            1.0 / self.tick_rate
+        }
+
+        // Number of ticks since the start
+        pub fn ticks(&self) -> u64 {
+            self.ticks
+        }
+
+        // Adds the time of a frame, returns how many ticks have to be simulated.
+        pub fn advance(&mut self, frame_time: f32) -> u32 {
+            let delta_time = self.delta_time();
+            self.accumulator += frame_time.max(0.0);
+
+            let mut steps = 0;
+            while self.accumulator >= delta_time {
+                if steps == self.max_steps {
+                    let remainder = self.accumulator % delta_time;
+                    self.skipped += self.accumulator - remainder;
+                    self.accumulator = remainder;
+                    break;
+                }
+
+                self.accumulator -= delta_time;
+                steps += 1;
+            }
+
+            self.ticks += steps as u64;
+
+            if self.skipped > 0.0 && self.ticks >= self.next_warning {
+                log::warn!("Simulation is falling behind, skipped {:.3}s", self.skipped);
+                self.skipped = 0.0;
+                self.next_warning = self.ticks + self.tick_rate.ceil() as u64;
+            }
+
+            steps
+        }
+
+        // Between 0 and 1, how far the current frame is between the last tick and the next one.
+        pub fn alpha(&self) -> f32 {
+            (self.accumulator / self.delta_time()).clamp(0.0, 1.0)
+        }
+    }
+}
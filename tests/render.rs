@@ -0,0 +1,152 @@
+// Renders small scenes offscreen and checks a few pixels of the results. The
+// tests are skipped on machines without any adapter, not even a software one.
+
+use wgpu_3d_engine::{BACKGROUND_COLOR, State};
+use wgpu_3d_engine::camera::camera::CameraMode;
+use wgpu_3d_engine::light::light::Light;
+use wgpu_3d_engine::material::material::Material;
+use wgpu_3d_engine::object::object::gmlib::matrix::Vec3;
+use wgpu_3d_engine::object::object::{Object, Triangle, Vertex};
+use wgpu_3d_engine::rasterizer::rasterizer::{Framebuffer, Rasterizer};
+
+const SIZE: u32 = 64;
+const BACKGROUND: [u8; 4] = [89, 203, 255, 255];
+
+fn headless() -> Option<State> {
+    match pollster::block_on(State::new_headless(SIZE, SIZE)) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            eprintln!("Skipping, no adapter: {}", e);
+            None
+        }
+    }
+}
+
+// A square facing the camera, which looks down the z axis from the origin
+fn square(size: f32, z: f32, color: [f32; 4]) -> Object {
+    let triangle = |corners: [[f32; 2]; 3]| Triangle::new(corners.map(|[x, y]| Vertex::new([x * size, y * size, z], color)));
+
+    Object::new([0.0, 0.0, 0.0], vec![
+        triangle([[-0.5, -0.5], [0.5, -0.5], [0.5, 0.5]]),
+        triangle([[-0.5, -0.5], [0.5, 0.5], [-0.5, 0.5]]),
+    ], false)
+}
+
+// A square facing +z with normals, so it is lit
+fn lit_square(size: f32, z: f32) -> Object {
+    let triangle = |corners: [[f32; 2]; 3]| {
+        let mut triangle = Triangle::new(corners.map(|[x, y]| Vertex::new([x * size, y * size, z], [1.0; 4])));
+        for vertex in triangle.vertices.iter_mut() {
+            vertex.normal = Vec3::from([0.0, 0.0, 1.0]);
+        }
+        triangle
+    };
+
+    Object::new([0.0, 0.0, 0.0], vec![
+        triangle([[-0.5, -0.5], [0.5, 0.5], [0.5, -0.5]]),
+        triangle([[-0.5, -0.5], [-0.5, 0.5], [0.5, 0.5]]),
+    ], false)
+}
+
+fn assert_pixel(image: &image::RgbaImage, x: u32, y: u32, expected: [u8; 4]) {
+    let pixel = image.get_pixel(x, y).0;
+    let close = pixel.iter().zip(expected).all(|(&value, expected)| value.abs_diff(expected) <= 2);
+
+    assert!(close, "pixel ({}, {}) is {:?}, expected {:?}", x, y, pixel, expected);
+}
+
+#[test]
+fn renders_vertex_colors_over_the_background() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+
+    // The green square is larger and further away, the red one covers its center
+    state.add_object(square(1.0, 3.0, [1.0, 0.0, 0.0, 1.0]));
+    state.add_object(square(3.0, 4.0, [0.0, 1.0, 0.0, 1.0]));
+
+    let image = state.render_image().unwrap();
+    assert_eq!(image.dimensions(), (SIZE, SIZE));
+
+    let center = SIZE / 2;
+    assert_pixel(&image, center, center, [255, 0, 0, 255]);
+    assert_pixel(&image, center, center + 14, [0, 255, 0, 255]);
+    assert_pixel(&image, 0, 0, BACKGROUND);
+}
+
+#[test]
+fn renders_lit_squares_over_the_background() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+
+    let camera = state.camera_mut();
+    camera.position = [0.0, 0.0, 3.0];
+    camera.set_mode(CameraMode::Free);
+    camera.look_at([0.0, 0.0, 0.0]);
+
+    // Half the light on a surface facing it, without highlights, gives 0.5 in
+    // linear space, 188 in sRGB
+    state.set_ambient_light([0.0, 0.0, 0.0]);
+    state.add_light(Light::directional([0.0, 0.0, -1.0], [1.0, 1.0, 1.0], 0.5));
+
+    let red = state.add_material(Material {
+        specular: 0.0,
+        ..Material::color([1.0, 0.0, 0.0, 1.0])
+    });
+    let blue = state.add_material(Material {
+        specular: 0.0,
+        ..Material::color([0.0, 0.0, 1.0, 1.0])
+    });
+
+    // The blue square is larger and further away, the red one covers its center
+    let front = state.add_object(lit_square(1.0, 0.0));
+    let back = state.add_object(lit_square(2.0, -1.0));
+    state.set_material(front, Some(red));
+    state.set_material(back, Some(blue));
+
+    let image = state.render_image().unwrap();
+    assert_eq!(image.dimensions(), (SIZE, SIZE));
+
+    let center = SIZE / 2;
+    assert_pixel(&image, center, center, [188, 0, 0, 255]);
+    assert_pixel(&image, center, center + 10, [0, 0, 188, 255]);
+    assert_pixel(&image, 0, 0, BACKGROUND);
+}
+
+#[test]
+fn rasterizer_matches_the_gpu() {
+    let Some(mut state) = headless() else {
+        return;
+    };
+
+    // Overlapping squares and a triangle with a color per corner, so depth
+    // testing and color interpolation are compared as well
+    state.add_object(square(1.0, 3.0, [1.0, 0.0, 0.0, 1.0]));
+    state.add_object(square(3.0, 4.0, [0.0, 1.0, 0.0, 1.0]));
+    state.add_object(Object::new([0.0, 0.0, 0.0], vec![Triangle::new([
+        Vertex::new([-1.5, -1.0, 3.5], [1.0, 0.0, 0.0, 1.0]),
+        Vertex::new([0.5, -1.0, 3.5], [0.0, 1.0, 0.0, 1.0]),
+        Vertex::new([-0.5, 1.0, 3.5], [0.0, 0.0, 1.0, 1.0]),
+    ])], false));
+
+    let gpu = state.render_image().unwrap();
+
+    let mut framebuffer = Framebuffer::new(SIZE, SIZE);
+    framebuffer.clear([
+        BACKGROUND_COLOR.r as f32,
+        BACKGROUND_COLOR.g as f32,
+        BACKGROUND_COLOR.b as f32,
+        BACKGROUND_COLOR.a as f32,
+    ]);
+    Rasterizer::new().render(&mut framebuffer, state.objects(), state.camera());
+    let cpu = framebuffer.to_image();
+
+    // Pixels on the edges of triangles may be covered by only one of them
+    let different = gpu.pixels()
+        .zip(cpu.pixels())
+        .filter(|(gpu, cpu)| gpu.0.iter().zip(cpu.0).any(|(&gpu, cpu)| gpu.abs_diff(cpu) > 2))
+        .count();
+
+    assert!(different <= (SIZE * SIZE / 50) as usize, "{} of {} pixels differ", different, SIZE * SIZE);
+}